@@ -1,17 +1,20 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 
 use serde_derive::{Deserialize, Serialize};
 
-use crate::representation::tree::{
-    filters::{FilterAggregate, GitignoreFilter},
-    iterator::TreeIter,
-};
+use crate::blob::definitions::BlobDefinition;
+use crate::blob::workspace::project_tree_iter;
+use crate::representation::tree::iterator::TreeIter;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProjectAnalysisDraft {
     pub path_root: String,
     pub prompt: String,
     // pub structure: String,
+    /// Project definitions, each file is analyzed with the ones scoped to it.
+    #[serde(default)]
+    pub definitions: Vec<BlobDefinition>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,8 +32,13 @@ pub struct ProjectAnalysisResult {
 }
 
 impl ProjectAnalysisDraft {
+    #[allow(dead_code)]
     pub fn new(path_root: String, prompt: String) -> Self {
-        ProjectAnalysisDraft { path_root, prompt }
+        ProjectAnalysisDraft {
+            path_root,
+            prompt,
+            definitions: vec![],
+        }
     }
 
     pub fn new_with_default_prompt(path_root: String) -> Self {
@@ -44,16 +52,57 @@ impl ProjectAnalysisDraft {
 
 In your summary, please explicitly state any assumptions or contextual information necessary to understand the code and its behavior within the larger system. Additionally, use appropriate references to any external dependencies, data sources, or other related code snippets as needed.
 ".to_string(),
+            definitions: vec![],
         }
     }
 
     pub fn tree_iter(&mut self) -> Box<TreeIter> {
-        let mut filters = FilterAggregate::default();
-        let root = Path::new(&self.path_root).to_owned();
-        let github_filter = GitignoreFilter::new(root.clone()).unwrap().unwrap();
+        project_tree_iter(&self.path_root)
+    }
+}
+
+impl ProjectAnalysisResult {
+    /// Successful analyses keyed by their path relative to the project root.
+    pub fn summaries(&self) -> BTreeMap<String, String> {
+        self.source_files
+            .iter()
+            .filter_map(|source| {
+                let result = source.result.as_ref()?;
+                let relative = relative_file_path(&self.parent.path_root, &source.file_path);
+
+                Some((relative, result.clone()))
+            })
+            .collect()
+    }
+
+    /// First sentence of every summary, see [`one_line_summary`].
+    pub fn one_line_summaries(&self) -> BTreeMap<String, String> {
+        self.summaries()
+            .into_iter()
+            .map(|(file, summary)| (file, one_line_summary(&summary)))
+            .collect()
+    }
+}
+
+/// Normalize a walked path (`./src/main.rs`, `<root>/src/main.rs`) to `src/main.rs`.
+pub fn relative_file_path(path_root: &str, file_path: &str) -> String {
+    let relative = Path::new(file_path)
+        .strip_prefix(path_root)
+        .unwrap_or_else(|_| Path::new(file_path));
+
+    relative
+        .to_string_lossy()
+        .trim_start_matches("./")
+        .trim_start_matches('/')
+        .to_string()
+}
 
-        filters.push(github_filter);
+/// First sentence of an analysis, good enough to label a file.
+pub fn one_line_summary(analysis: &str) -> String {
+    let first_line = analysis.trim().lines().next().unwrap_or_default();
 
-        Box::new(TreeIter::new(root, filters).unwrap())
+    match first_line.find(". ") {
+        Some(end) => first_line[..=end].to_string(),
+        None => first_line.to_string(),
     }
 }
@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::representation::tree::filters::build_glob_set;
+use crate::representation::tree::folding::TreeLimits;
+use crate::representation::tree::formats::TreeFormat;
+use crate::representation::tree::links::SymlinkPolicy;
+use crate::representation::tree::order::{SortOrder, TreeOrder};
+
+/// Project level settings, read from `.blob/config.toml`.
+/// Every section is optional, missing keys fall back to their defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlobConfig {
+    pub check: CheckConfig,
+    pub git: GitConfig,
+    pub context: ContextConfig,
+    pub llm: LlmConfig,
+    pub filters: FilterConfig,
+    pub redaction: RedactionConfig,
+    pub representation: RepresentationConfig,
+    pub tree: TreeConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckWorkspace {
+    /// Run the check in a throwaway copy of the project.
+    Scratch,
+    /// Run the check in the project itself, restoring the original files afterwards.
+    /// Project mutations are always checked in a scratch copy.
+    WorkingTree,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CheckConfig {
+    /// Command used to verify a mutation, e.g. `cargo check` or `pytest -x`.
+    pub command: Option<String>,
+    /// How many times the model is asked to repair a mutation that fails the check.
+    pub max_repairs: usize,
+    pub workspace: CheckWorkspace,
+}
+
+impl Default for CheckConfig {
+    fn default() -> Self {
+        CheckConfig {
+            command: None,
+            max_repairs: 3,
+            workspace: CheckWorkspace::Scratch,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommitMode {
+    /// Leave the applied changes uncommitted.
+    #[default]
+    Off,
+    /// Commit every applied mutation on the current branch.
+    Commit,
+    /// Commit every applied mutation on a new `blob/<id>` branch.
+    Branch,
+}
+
+impl CommitMode {
+    /// `--branch` wins over `--commit`, without flags the configured mode is used.
+    pub fn from_flags(commit: bool, branch: bool, configured: CommitMode) -> Self {
+        match (commit, branch) {
+            (_, true) => CommitMode::Branch,
+            (true, false) => CommitMode::Commit,
+            (false, false) => configured,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GitConfig {
+    pub commit: CommitMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextConfig {
+    /// Estimated tokens of project definitions given to the model per mutation.
+    pub max_tokens: usize,
+    /// Also rank the definitions describing blob itself, see `blob define self-reference`.
+    pub include_self_reference: bool,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        ContextConfig {
+            max_tokens: 1000,
+            include_self_reference: false,
+        }
+    }
+}
+
+/// Which files are walked for the tree representation and `blob analyze`, on top of
+/// the `.gitignore` and `.blobignore` files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    /// When not empty, only files matching one of these globs are walked.
+    pub include: Vec<String>,
+    /// Files and folders matching one of these globs are left out.
+    pub exclude: Vec<String>,
+    /// Files bigger than this many bytes are left out.
+    pub max_file_size: u64,
+    /// Walk files and folders starting with a dot.
+    pub hidden: bool,
+    /// Walk files that look binary.
+    pub binary: bool,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            include: vec![],
+            exclude: vec![],
+            max_file_size: 1024 * 1024,
+            hidden: true,
+            binary: false,
+        }
+    }
+}
+
+/// How the project structure is written for the model, see `blob eval-repr`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepresentationConfig {
+    pub format: TreeFormat,
+    /// Follow the file names with their size, lines, language and `blob analyze` summary.
+    pub annotate: bool,
+    /// Format per model name, e.g. `"gpt-3.5-turbo" = "outline"`, wins over `format`.
+    pub models: BTreeMap<String, TreeFormat>,
+}
+
+impl RepresentationConfig {
+    pub fn format_for(&self, model: &str) -> TreeFormat {
+        self.models.get(model).copied().unwrap_or(self.format)
+    }
+}
+
+/// Order and size of the structure given to the model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TreeConfig {
+    pub sort: SortOrder,
+    /// List the folders before the files of each folder.
+    pub dirs_first: bool,
+    /// Deepest level listed, unlimited when not set.
+    pub max_depth: Option<usize>,
+    /// Folders with more children are folded into a summary line, unless the
+    /// instruction mentions them.
+    pub fold_threshold: Option<usize>,
+    /// Skip, show or follow symbolic links.
+    pub symlinks: SymlinkPolicy,
+}
+
+impl Default for TreeConfig {
+    fn default() -> Self {
+        let order = TreeOrder::default();
+
+        TreeConfig {
+            sort: order.sort,
+            dirs_first: order.dirs_first,
+            max_depth: None,
+            fold_threshold: Some(100),
+            symlinks: SymlinkPolicy::default(),
+        }
+    }
+}
+
+impl TreeConfig {
+    pub fn order(&self) -> TreeOrder {
+        TreeOrder {
+            sort: self.sort,
+            dirs_first: self.dirs_first,
+        }
+    }
+
+    pub fn limits(&self) -> TreeLimits {
+        TreeLimits {
+            max_depth: self.max_depth,
+            fold_threshold: self.fold_threshold,
+            ..TreeLimits::default()
+        }
+    }
+}
+
+/// Secrets masked in everything sent to the model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    /// Extra regular expressions, every match is redacted.
+    pub patterns: Vec<String>,
+    /// Shannon entropy (bits per character) from which long tokens are redacted.
+    pub entropy_threshold: f64,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        RedactionConfig {
+            enabled: true,
+            patterns: vec![],
+            entropy_threshold: 4.0,
+        }
+    }
+}
+
+/// OpenAI compatible backend used for completions and edits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LlmConfig {
+    pub base_url: String,
+    pub completion_model: String,
+    pub edit_model: String,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        LlmConfig {
+            base_url: "https://api.openai.com/v1".to_string(),
+            completion_model: "text-davinci-003".to_string(),
+            edit_model: "code-davinci-edit-001".to_string(),
+        }
+    }
+}
+
+impl LlmConfig {
+    /// A backend on this machine, which doesn't need an API key.
+    pub fn is_local(&self) -> bool {
+        let url = match reqwest::Url::parse(&self.base_url) {
+            Ok(url) => url,
+            Err(_) => return false,
+        };
+
+        matches!(
+            url.host_str(),
+            Some("localhost" | "127.0.0.1" | "[::1]" | "0.0.0.0")
+        )
+    }
+}
+
+/// Written by `blob init`, every setting is commented out with its default value.
+const CONFIG_TEMPLATE: &str = r#"# Blob settings, every key is optional and shows its default value.
+
+[llm]
+# OpenAI compatible API. Point it to a local server, e.g. "http://localhost:8080/v1",
+# to work without OPENAI_API_KEY.
+# base_url = "https://api.openai.com/v1"
+# completion_model = "text-davinci-003"
+# edit_model = "code-davinci-edit-001"
+
+[check]
+# Command that must pass before a mutation is offered, e.g. "cargo check".
+# command = "cargo check"
+# max_repairs = 3
+# "scratch" or "working-tree".
+# workspace = "scratch"
+
+[git]
+# "off", "commit" or "branch".
+# commit = "off"
+
+[context]
+# Estimated tokens of definitions given to the model per mutation.
+# max_tokens = 1000
+# include_self_reference = false
+
+[filters]
+# Files walked for the tree and `blob analyze`, on top of .gitignore and .blobignore.
+# include = ["src/**", "*.md"]
+# exclude = ["*.lock", "vendor", "fixtures"]
+# max_file_size = 1048576
+# hidden = true
+# binary = false
+
+[tree]
+# "natural" (file2 before file10, case insensitive) or "name" (byte order).
+# sort = "natural"
+# dirs_first = true
+# Keep the structure small in large repositories, paths mentioned by the
+# instruction are always listed.
+# max_depth = 4
+# fold_threshold = 100
+# "show" lists links with their target, "skip" leaves them out and "follow"
+# walks them, except the ones leading back to a parent folder or out of the project.
+# symlinks = "show"
+
+[redaction]
+# Secrets are masked before anything is sent to the model.
+# enabled = true
+# patterns = ["internal-[0-9a-f]{32}"]
+# entropy_threshold = 4.0
+
+[representation]
+# How the project structure is given to the model: "tree", "ascii", "paths",
+# "outline", "json" or "yaml". Compare them with `blob eval-repr`.
+# format = "tree"
+# Follow file names with their size, lines, language and `blob analyze` summary.
+# annotate = false
+
+[representation.models]
+# Format for a given model, it wins over `format`.
+# "code-davinci-edit-001" = "tree"
+"#;
+
+impl BlobConfig {
+    pub fn path(project_root: &str) -> String {
+        format!("{project_root}/.blob/config.toml")
+    }
+
+    /// Write the commented config template, returns `false` when a config already exists.
+    pub fn write_template(project_root: &str) -> Result<bool> {
+        let path = BlobConfig::path(project_root);
+
+        if Path::new(&path).exists() {
+            return Ok(false);
+        }
+
+        std::fs::write(&path, CONFIG_TEMPLATE)?;
+
+        Ok(true)
+    }
+
+    pub fn load(project_root: &str) -> Result<Self> {
+        let path = BlobConfig::path(project_root);
+
+        if !Path::new(&path).exists() {
+            return Ok(BlobConfig::default());
+        }
+
+        let content = read_to_string(&path)?;
+
+        let config: BlobConfig =
+            toml::from_str(&content).with_context(|| format!("Invalid config file '{path}'"))?;
+
+        for pattern in config.filters.include.iter().chain(&config.filters.exclude) {
+            build_glob_set(std::slice::from_ref(pattern))
+                .map_err(|err| anyhow!("Invalid glob '{pattern}' in '{path}': {err}"))?;
+        }
+
+        Ok(config)
+    }
+}
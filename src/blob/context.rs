@@ -2,9 +2,10 @@ use chrono::serde::ts_seconds;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
-use std::fs::OpenOptions;
+use std::collections::HashSet;
 use std::io::Read;
 use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
 use std::{
     fs::{create_dir_all, File},
     io::Write,
@@ -12,21 +13,53 @@ use std::{
 
 use chrono::{DateTime, Utc};
 
+use anyhow::{anyhow, Result};
+
+use crate::blob::analysis::{relative_file_path, ProjectAnalysisResult};
+use crate::blob::config::ContextConfig;
+use crate::blob::definitions::{
+    builtin_self_reference_definitions, parse_legacy_definitions, BlobDefinition,
+};
+use crate::blob::mutation::mutation_id;
 use crate::blob::mutation::ProjectMutation;
+use crate::blob::mutation::ProjectMutationDraft;
+use crate::blob::mutation::ProjectMutationProposed;
+use crate::blob::mutation::ProjectMutationStage;
 use crate::blob::mutation::SourceFileMutation;
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-// #[serde(rename_all = "camelCase")]
-pub struct BlobDefinition {
-    #[serde(with = "ts_seconds")]
-    pub created_at: DateTime<Utc>,
-    pub definition: String,
-}
+use crate::blob::mutation::SourceFileMutationGroup;
+use crate::blob::plan::{MutationPlan, MutationPlanStep};
+use crate::blob::ranking::ContextSelection;
+use crate::representation::tree::annotations::Annotations;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BlobMutationMetadataKind {
+    /// A project mutation stopped before its structure was proposed.
+    ProjectDraft(Box<ProjectMutationDraft>),
+    /// A project mutation stopped before its commands were generated.
+    ProjectProposed(Box<ProjectMutationProposed>),
     Project(Box<ProjectMutation>),
     SourceFile(Box<SourceFileMutation>),
+    Plan(Box<MutationPlan>),
+    SourceFileGroup(Box<SourceFileMutationGroup>),
+}
+
+impl BlobMutationMetadataKind {
+    /// Last saved stage of a project mutation, `None` for the other kinds. A finished
+    /// mutation is resumed from its proposed structure.
+    pub fn project_stage(self) -> Option<ProjectMutationStage> {
+        match self {
+            BlobMutationMetadataKind::ProjectDraft(draft) => {
+                Some(ProjectMutationStage::Drafted(draft))
+            }
+            BlobMutationMetadataKind::ProjectProposed(proposal) => {
+                Some(ProjectMutationStage::Proposed(proposal))
+            }
+            BlobMutationMetadataKind::Project(mutation) => {
+                Some(ProjectMutationStage::Proposed(mutation.parent))
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,18 +69,36 @@ pub struct BlobMutationMetadata {
     pub created_at: DateTime<Utc>,
     pub kind: BlobMutationKind,
     pub mutation: BlobMutationMetadataKind,
+    #[serde(default)]
+    pub commit: Option<BlobMutationCommit>,
+}
+
+/// The git commit created for an applied mutation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobMutationCommit {
+    pub commit_id: String,
+    pub branch: Option<String>,
+    pub reverted_by: Option<String>,
+    /// Commit of an earlier application, reverted before the mutation was resumed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<Box<BlobMutationCommit>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BlobMutationKind {
+    ProjectDraft,
+    ProjectProposed,
     Project,
     SourceFile,
+    Plan,
+    SourceFileGroup,
 }
 
 pub struct BlobContextProcessor {
     project_path: String,
 }
 
+#[derive(Clone)]
 pub enum BlobDefinitionKind {
     Project,
     SelfReference,
@@ -56,65 +107,211 @@ pub enum BlobDefinitionKind {
 impl BlobDefinitionKind {
     fn as_filename(&self) -> &'static str {
         match self {
-            BlobDefinitionKind::Project => "_project.md",
-            BlobDefinitionKind::SelfReference => "_self.md",
-            // BlobDefinitionKind::Meta => "meta.md",
+            BlobDefinitionKind::Project => "project.jsonl",
+            BlobDefinitionKind::SelfReference => "self.jsonl",
+            // BlobDefinitionKind::Meta => "meta.jsonl",
+        }
+    }
+
+    /// The old `_self.md` was regenerated on every run, so it isn't worth migrating.
+    fn legacy_filename(&self) -> Option<&'static str> {
+        match self {
+            BlobDefinitionKind::Project => Some("_project.md"),
+            BlobDefinitionKind::SelfReference => None,
         }
     }
 }
 
 impl BlobContextProcessor {
     pub fn new(project_path: String) -> Self {
-        let ctx = Self { project_path };
+        Self { project_path }
+    }
+
+    /// Definitions describing blob itself. A project can override the ones shipped
+    /// with the binary by writing its own `self.jsonl`.
+    pub fn retrieve_self_reference_definitions(&self) -> Vec<BlobDefinition> {
+        match Path::new(&self.get_definitions_file_path(&BlobDefinitionKind::SelfReference))
+            .exists()
+        {
+            true => self.retrieve_definitions(BlobDefinitionKind::SelfReference),
+            false => builtin_self_reference_definitions(),
+        }
+    }
+
+    /// Write the built-in self-reference definitions into the project so they can be
+    /// edited, returns `None` when the project already has its own.
+    pub fn write_self_reference_definitions(&self, force: bool) -> Result<Option<String>> {
+        let file_path = self.get_definitions_file_path(&BlobDefinitionKind::SelfReference);
+
+        if Path::new(&file_path).exists() && !force {
+            return Ok(None);
+        }
+
+        self.write_definitions(
+            &BlobDefinitionKind::SelfReference,
+            &builtin_self_reference_definitions(),
+        )?;
 
-        ctx.save_definitions(BlobDefinitionKind::SelfReference, false, vec![
-            "blob is a cli tool to modify the source code with natural language instructions".to_string(),
-            "blob uses OpenAI GPT-3 to understand the instructions and execute the mutations in form of unix instructions or file editing".to_string(),
-            "blob have two commands: `blob do` an `blob define`".to_string(),
-            "`blob do \"YOUR_INSTRUCTION\"` is used to execute a mutation over the entire project file structure".to_string(),
-            "`blob do -f <file> \"YOUR_INSTRUCTION\"` is used to execute a mutation over specific file".to_string(),
-            "`blob define \"YOUR_DEFINITION\"` is used to define a concept and improve the context for blob".to_string(),
-        ]);
+        Ok(Some(file_path))
+    }
 
-        ctx
+    /// Create the `.blob` layout, returns the folders that didn't exist yet.
+    pub fn init_workspace(&self) -> Vec<String> {
+        [self.get_definitions_path(), self.get_mutations_path()]
+            .into_iter()
+            .filter(|path| !Path::new(path).exists())
+            .inspect(|path| create_dir_all(path).unwrap())
+            .collect()
     }
 
     fn get_definitions_path(&self) -> String {
         format!("{}/.blob/.definitions", self.project_path)
     }
 
+    fn get_analysis_path(&self) -> String {
+        format!("{}/.blob/.analysis", self.project_path)
+    }
+
     fn get_mutations_path(&self) -> String {
         format!("{}/.blob/.mutations", self.project_path)
     }
 
+    fn get_mutation_path(&self, id: &str) -> String {
+        format!("{}/{}", self.get_mutations_path(), id)
+    }
+
     fn get_project_mutation_path(&self, project_mutation: ProjectMutation) -> String {
-        let context_name = project_mutation
-            .parent
-            .parent
-            .created_at
-            .format("%Y%m%d%H%M%S")
-            .to_string();
+        self.get_mutation_path(&mutation_id(&project_mutation.parent.parent.created_at))
+    }
 
-        let mutations_path = self.get_mutations_path();
+    fn get_source_file_mutation_path(&self, source_file_mutation: SourceFileMutation) -> String {
+        self.get_mutation_path(&mutation_id(&source_file_mutation.parent.created_at))
+    }
 
-        format!("{mutations_path}/{context_name}")
+    fn get_plan_path(&self, plan: &MutationPlan) -> String {
+        self.get_mutation_path(&plan.id())
     }
 
-    fn get_source_file_mutation_path(&self, source_file_mutation: SourceFileMutation) -> String {
-        let context_name = source_file_mutation
-            .parent
-            .created_at
-            .format("%Y%m%d%H%M%S")
-            .to_string();
+    fn get_plan_step_path(&self, plan: &MutationPlan, step: &MutationPlanStep) -> String {
+        self.get_mutation_path(&step.mutation_id(plan))
+    }
+
+    /// Save (or refresh) the plan record, steps are stored as regular mutations under it.
+    pub fn save_mutation_plan(&self, plan: MutationPlan) -> String {
+        let plan_path = self.get_plan_path(&plan);
+        let metadata_path = format!("{plan_path}/metadata.json");
+
+        create_dir_all(plan_path.clone()).unwrap();
+
+        let mut metadata_file = File::create(metadata_path).unwrap();
 
-        let mutations_path = self.get_mutations_path();
+        let metadata = BlobMutationMetadata {
+            created_at: plan.parent.created_at,
+            kind: BlobMutationKind::Plan,
+            mutation: BlobMutationMetadataKind::Plan(Box::new(plan)),
+            commit: None,
+        };
+
+        let metadata_json = serde_json::to_string(&metadata).unwrap();
 
-        format!("{mutations_path}/{context_name}")
+        metadata_file.write_all(metadata_json.as_bytes()).unwrap();
+
+        plan_path
+    }
+
+    pub fn save_plan_step_project_mutation(
+        &self,
+        plan: &MutationPlan,
+        step: &MutationPlanStep,
+        project_mutation: ProjectMutation,
+    ) -> String {
+        self.write_project_mutation(self.get_plan_step_path(plan, step), project_mutation, None)
+    }
+
+    pub fn save_plan_step_source_file_mutation(
+        &self,
+        plan: &MutationPlan,
+        step: &MutationPlanStep,
+        source_file_mutation: SourceFileMutation,
+    ) -> String {
+        self.write_source_file_mutation(self.get_plan_step_path(plan, step), source_file_mutation)
+    }
+
+    /// Save the draft of a project mutation under `id`, so it can be resumed if the
+    /// next stages fail. Every later stage replaces it.
+    pub fn save_project_mutation_draft(&self, id: &str, draft: ProjectMutationDraft) -> String {
+        let new_context_path = self.get_mutation_path(id);
+
+        create_dir_all(new_context_path.clone()).unwrap();
+
+        let metadata = BlobMutationMetadata {
+            created_at: draft.created_at,
+            kind: BlobMutationKind::ProjectDraft,
+            mutation: BlobMutationMetadataKind::ProjectDraft(Box::new(draft)),
+            commit: self.previous_commit(id),
+        };
+
+        self.write_metadata(&new_context_path, &metadata);
+
+        new_context_path
+    }
+
+    /// Save the proposed structure of a project mutation under `id`, resuming from it
+    /// skips the structure proposal.
+    pub fn save_project_mutation_proposal(
+        &self,
+        id: &str,
+        proposal: ProjectMutationProposed,
+    ) -> String {
+        let new_context_path = self.get_mutation_path(id);
+
+        create_dir_all(new_context_path.clone()).unwrap();
+
+        let metadata = BlobMutationMetadata {
+            created_at: proposal.parent.created_at,
+            kind: BlobMutationKind::ProjectProposed,
+            mutation: BlobMutationMetadataKind::ProjectProposed(Box::new(proposal)),
+            commit: self.previous_commit(id),
+        };
+
+        self.write_metadata(&new_context_path, &metadata);
+
+        new_context_path
+    }
+
+    /// Save a resumed project mutation back where it was found, along with the
+    /// commit and revert of an earlier application.
+    pub fn save_resumed_project_mutation(
+        &self,
+        id: &str,
+        project_mutation: ProjectMutation,
+    ) -> String {
+        self.write_project_mutation(
+            self.get_mutation_path(id),
+            project_mutation,
+            self.previous_commit(id),
+        )
     }
 
     pub fn save_project_mutation(&self, project_mutation: ProjectMutation) -> String {
         let new_context_path = self.get_project_mutation_path(project_mutation.clone());
 
+        self.write_project_mutation(new_context_path, project_mutation, None)
+    }
+
+    /// Commit recorded for `id`, a mutation resumed after `blob reject` keeps it until
+    /// it's committed again.
+    fn previous_commit(&self, id: &str) -> Option<BlobMutationCommit> {
+        self.retrieve_mutation_metadata(id)
+            .and_then(|metadata| metadata.commit)
+    }
+
+    fn write_project_mutation(
+        &self,
+        new_context_path: String,
+        project_mutation: ProjectMutation,
+        commit: Option<BlobMutationCommit>,
+    ) -> String {
         let final_script_path = format!("{new_context_path}/script.sh");
         let metadata_path = format!("{new_context_path}/metadata.json");
 
@@ -138,6 +335,7 @@ impl BlobContextProcessor {
             created_at: project_mutation.parent.parent.created_at,
             kind: BlobMutationKind::Project,
             mutation: BlobMutationMetadataKind::Project(Box::new(project_mutation)),
+            commit,
         };
 
         let metadata_json = serde_json::to_string(&metadata).unwrap();
@@ -150,32 +348,117 @@ impl BlobContextProcessor {
     pub fn save_source_file_mutation(&self, source_file_mutation: SourceFileMutation) -> String {
         let new_context_path = self.get_source_file_mutation_path(source_file_mutation.clone());
 
-        // source_file_mutation.clone().parent.file_path
-        let mutated_source_file_path = format!(
-            "{}/{}",
-            new_context_path,
-            source_file_mutation.clone().parent.file_path
-        );
+        self.write_source_file_mutation(new_context_path, source_file_mutation)
+    }
 
-        let directories_only = source_file_mutation
-            .clone()
-            .parent
-            .file_path
-            .clone()
-            .split("/")
-            .collect::<Vec<&str>>()
-            .split_last()
-            .unwrap()
-            .1
-            .join("/");
+    fn write_source_file_mutation(
+        &self,
+        new_context_path: String,
+        source_file_mutation: SourceFileMutation,
+    ) -> String {
+        let mutated_source_file_path =
+            self.write_mutated_source_file(&new_context_path, &source_file_mutation);
 
-        let directories_mutated_source_file_path = format!("{new_context_path}/{directories_only}");
+        let metadata = BlobMutationMetadata {
+            created_at: source_file_mutation.parent.created_at,
+            kind: BlobMutationKind::SourceFile,
+            mutation: BlobMutationMetadataKind::SourceFile(Box::new(source_file_mutation)),
+            commit: None,
+        };
+
+        self.write_metadata(&new_context_path, &metadata);
+
+        mutated_source_file_path
+    }
+
+    /// Save every file of the group under a single mutation folder and return the
+    /// saved path of each file, in the same order as `group.mutations`.
+    pub fn save_source_file_mutation_group(
+        &self,
+        group: SourceFileMutationGroup,
+    ) -> (String, Vec<String>) {
+        let new_context_path = self.get_mutation_path(&mutation_id(&group.created_at));
 
-        create_dir_all(directories_mutated_source_file_path.clone()).unwrap();
+        create_dir_all(new_context_path.clone()).unwrap();
+
+        let mutated_source_file_paths = group
+            .mutations
+            .iter()
+            .map(|mutation| self.write_mutated_source_file(&new_context_path, mutation))
+            .collect();
+
+        let metadata = BlobMutationMetadata {
+            created_at: group.created_at,
+            kind: BlobMutationKind::SourceFileGroup,
+            mutation: BlobMutationMetadataKind::SourceFileGroup(Box::new(group)),
+            commit: None,
+        };
+
+        self.write_metadata(&new_context_path, &metadata);
+
+        (new_context_path, mutated_source_file_paths)
+    }
 
-        // let mutated_source_file_path = format!("{mutated_script_path}/script.sh");
+    pub fn retrieve_mutation_metadata(&self, id: &str) -> Option<BlobMutationMetadata> {
+        let metadata_path = format!("{}/metadata.json", self.get_mutation_path(id));
+
+        let mut file = File::open(metadata_path).ok()?;
+
+        let mut contents = String::new();
+
+        file.read_to_string(&mut contents).ok()?;
+
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn record_mutation_commit(&self, id: &str, mut commit: BlobMutationCommit) {
+        if let Some(mut metadata) = self.retrieve_mutation_metadata(id) {
+            // A new commit of a resumed mutation keeps the reverted one, the same commit
+            // recorded again (e.g. with its revert) already has it.
+            if let Some(previous) = metadata
+                .commit
+                .filter(|previous| previous.commit_id != commit.commit_id)
+            {
+                commit.previous = Some(Box::new(previous));
+            }
+
+            metadata.commit = Some(commit);
+
+            self.write_metadata(&self.get_mutation_path(id), &metadata);
+        }
+    }
+
+    fn write_metadata(&self, new_context_path: &str, metadata: &BlobMutationMetadata) {
         let metadata_path = format!("{new_context_path}/metadata.json");
 
+        let mut metadata_file = File::create(metadata_path).unwrap();
+
+        let metadata_json = serde_json::to_string(metadata).unwrap();
+
+        metadata_file.write_all(metadata_json.as_bytes()).unwrap();
+    }
+
+    fn write_mutated_source_file(
+        &self,
+        new_context_path: &str,
+        source_file_mutation: &SourceFileMutation,
+    ) -> String {
+        // Keep only the normal components so `./src/main.rs` and `/abs/src/main.rs`
+        // both end up inside the mutation folder.
+        let relative_file_path: PathBuf = Path::new(&source_file_mutation.parent.file_path)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+
+        let mutated_source_file_path = Path::new(new_context_path)
+            .join(relative_file_path)
+            .to_string_lossy()
+            .to_string();
+
+        if let Some(directories_only) = Path::new(&mutated_source_file_path).parent() {
+            create_dir_all(directories_only).unwrap();
+        }
+
         let mut file_source_file = File::create(mutated_source_file_path.clone()).unwrap();
 
         let source_file_content = source_file_mutation.clone().proposed_content;
@@ -184,101 +467,426 @@ impl BlobContextProcessor {
             .write_all(source_file_content.as_bytes())
             .unwrap();
 
-        let mut metadata_file = File::create(metadata_path.clone()).unwrap();
+        mutated_source_file_path
+    }
 
-        let metadata = BlobMutationMetadata {
-            created_at: source_file_mutation.parent.created_at,
-            kind: BlobMutationKind::SourceFile,
-            mutation: BlobMutationMetadataKind::SourceFile(Box::new(source_file_mutation)),
+    /// Keep the result of the last `blob analyze` run around for the other commands.
+    pub fn save_project_analysis(&self, analysis: ProjectAnalysisResult) -> String {
+        let analysis_root = self.get_analysis_path();
+
+        create_dir_all(analysis_root.clone()).unwrap();
+
+        let analysis_path = format!("{analysis_root}/latest.json");
+
+        let mut analysis_file = File::create(analysis_path.clone()).unwrap();
+
+        let analysis_json = serde_json::to_string(&analysis).unwrap();
+
+        analysis_file.write_all(analysis_json.as_bytes()).unwrap();
+
+        analysis_path
+    }
+
+    /// Annotations of the tree, with the summaries of the last `blob analyze` run.
+    /// Lines are only counted in files of at most `max_size` bytes.
+    pub fn tree_annotations(&self, max_size: u64) -> Annotations {
+        let summaries = self
+            .retrieve_project_analysis()
+            .map(|analysis| analysis.one_line_summaries())
+            .unwrap_or_default();
+
+        Annotations::new(PathBuf::from(&self.project_path), summaries, max_size)
+    }
+
+    pub fn retrieve_project_analysis(&self) -> Option<ProjectAnalysisResult> {
+        let analysis_path = format!("{}/latest.json", self.get_analysis_path());
+
+        let mut file = File::open(analysis_path).ok()?;
+
+        let mut contents = String::new();
+
+        file.read_to_string(&mut contents).ok()?;
+
+        serde_json::from_str(&contents).ok()
+    }
+
+    fn get_definitions_file_path(&self, kind: &BlobDefinitionKind) -> String {
+        format!("{}/{}", self.get_definitions_path(), kind.as_filename())
+    }
+
+    fn get_legacy_definitions_file_path(&self, kind: &BlobDefinitionKind) -> Option<String> {
+        kind.legacy_filename()
+            .map(|legacy_filename| format!("{}/{}", self.get_definitions_path(), legacy_filename))
+    }
+
+    /// Definitions of the old comma separated `.md` file, read in place until the next
+    /// write migrates them. Lines without a valid timestamp are dated with the file's
+    /// modification time so their ids don't change between reads.
+    fn retrieve_legacy_definitions(&self, kind: &BlobDefinitionKind) -> Vec<BlobDefinition> {
+        let legacy_path = match self.get_legacy_definitions_file_path(kind) {
+            Some(legacy_path) => legacy_path,
+            None => return vec![],
         };
 
-        let metadata_json = serde_json::to_string(&metadata).unwrap();
+        let contents = match std::fs::read_to_string(&legacy_path) {
+            Ok(contents) => contents,
+            Err(_) => return vec![],
+        };
 
-        metadata_file.write_all(metadata_json.as_bytes()).unwrap();
+        let modified = std::fs::metadata(&legacy_path)
+            .and_then(|metadata| metadata.modified())
+            .map(DateTime::<Utc>::from)
+            .unwrap_or(DateTime::<Utc>::from(std::time::UNIX_EPOCH));
+
+        let mut definitions = parse_legacy_definitions(&contents, modified);
+
+        let mut taken = HashSet::new();
 
-        mutated_source_file_path.to_string()
+        for definition in definitions.iter_mut() {
+            definition.ensure_unique_id(&taken);
+            taken.insert(definition.id.clone());
+        }
+
+        definitions
     }
 
+    /// Only reads, the old `.md` file is migrated by the next write of the definitions.
     pub fn retrieve_definitions(&self, kind: BlobDefinitionKind) -> Vec<BlobDefinition> {
-        let definitions_root = self.get_definitions_path();
-        let file_path = format!("{}/{}", definitions_root, kind.as_filename().to_string());
+        let file_path = self.get_definitions_file_path(&kind);
 
-        let mut definitions = Vec::new();
+        if !Path::new(&file_path).exists() {
+            return self.retrieve_legacy_definitions(&kind);
+        }
 
-        let mut file = match File::open(file_path) {
-            Ok(file) => file,
-            Err(_) => return definitions,
+        let contents = match std::fs::read_to_string(file_path) {
+            Ok(contents) => contents,
+            Err(_) => return vec![],
         };
 
-        let mut contents = String::new();
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(definition) => Some(definition),
+                Err(err) => {
+                    eprintln!("Skipping malformed definition ({err}): {line}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Lines of the definitions file that aren't definitions, e.g. a hand edit gone
+    /// wrong, are written back as they are rather than dropped.
+    fn write_definitions(
+        &self,
+        kind: &BlobDefinitionKind,
+        definitions: &[BlobDefinition],
+    ) -> Result<()> {
+        create_dir_all(self.get_definitions_path())?;
+
+        let file_path = self.get_definitions_file_path(kind);
+
+        // Without a JSONL file the definitions were read from the old `.md` one, which
+        // is kept next to it with a `.bak` extension once they're written.
+        let legacy_path = self
+            .get_legacy_definitions_file_path(kind)
+            .filter(|legacy_path| {
+                !Path::new(&file_path).exists() && Path::new(legacy_path).exists()
+            });
+
+        let existing = std::fs::read_to_string(&file_path).unwrap_or_default();
+
+        let mut written = HashSet::new();
+        let mut lines = Vec::new();
+
+        // Definitions keep their place, removed ones are left out and new ones go last.
+        for line in existing.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<BlobDefinition>(line) {
+                Ok(previous) => {
+                    if let Some(definition) = definitions
+                        .iter()
+                        .find(|def| def.id == previous.id && !written.contains(&def.id))
+                    {
+                        lines.push(serde_json::to_string(definition)?);
+                        written.insert(definition.id.clone());
+                    }
+                }
+                Err(_) => lines.push(line.to_string()),
+            }
+        }
 
-        file.read_to_string(&mut contents).unwrap();
+        for definition in definitions.iter().filter(|def| !written.contains(&def.id)) {
+            lines.push(serde_json::to_string(definition)?);
+        }
 
-        let lines = contents.split("\n");
+        let contents = lines
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
 
-        for line in lines {
-            let parts = line.split(",").collect::<Vec<&str>>();
+        std::fs::write(&file_path, contents)
+            .map_err(|err| anyhow!("Couldn't write {file_path}: {err}"))?;
 
-            if parts.len() == 2 {
-                let created_at_str = parts[0].trim();
-                let definition = parts[1].trim();
+        if let Some(legacy_path) = legacy_path {
+            std::fs::rename(&legacy_path, format!("{legacy_path}.bak"))?;
+        }
 
-                let created_at = DateTime::parse_from_rfc3339(created_at_str).unwrap();
+        Ok(())
+    }
 
-                let def = BlobDefinition {
-                    created_at: DateTime::from_utc(created_at.naive_utc(), Utc),
-                    definition: definition.to_string(),
-                };
+    fn append_definitions(
+        &self,
+        kind: BlobDefinitionKind,
+        new_definitions: Vec<BlobDefinition>,
+    ) -> Result<Vec<BlobDefinition>> {
+        let mut definitions = self.retrieve_definitions(kind.clone());
 
-                definitions.push(def);
-            }
+        let mut taken: HashSet<String> = definitions.iter().map(|def| def.id.clone()).collect();
+
+        let mut added = Vec::new();
+
+        for mut definition in new_definitions {
+            definition.ensure_unique_id(&taken);
+            taken.insert(definition.id.clone());
+
+            added.push(definition);
+        }
+
+        definitions.extend(added.clone());
+
+        self.write_definitions(&kind, &definitions)?;
+
+        Ok(added)
+    }
+
+    /// The git user name of the project, or the system user.
+    pub fn definition_author(&self) -> Option<String> {
+        git2::Repository::discover(&self.project_path)
+            .and_then(|repo| repo.config())
+            .and_then(|config| config.get_string("user.name"))
+            .ok()
+            .or_else(|| std::env::var("USER").ok())
+    }
+
+    /// Project definitions for a mutation of `file_path` (absolute or relative to the
+    /// project), ranked against the instruction within the configured token budget.
+    /// Without a file only unscoped definitions are considered.
+    pub fn select_project_context(
+        &self,
+        instruction: &str,
+        file_path: Option<&str>,
+        config: &ContextConfig,
+    ) -> ContextSelection {
+        let definitions = self.context_definitions(config);
+
+        let relative_path = file_path.map(|path| relative_file_path(&self.project_path, path));
+
+        ContextSelection::select(
+            &definitions,
+            instruction,
+            relative_path.as_deref(),
+            config.max_tokens,
+        )
+    }
+
+    /// Project definitions for changes to `paths`, relative to the project, ranked
+    /// against `query` within the configured token budget.
+    pub fn select_context_for_paths(
+        &self,
+        query: &str,
+        paths: &[String],
+        config: &ContextConfig,
+    ) -> ContextSelection {
+        ContextSelection::select_for_paths(
+            &self.context_definitions(config),
+            query,
+            paths,
+            config.max_tokens,
+        )
+    }
+
+    fn context_definitions(&self, config: &ContextConfig) -> Vec<BlobDefinition> {
+        let mut definitions = self.retrieve_definitions(BlobDefinitionKind::Project);
+
+        if config.include_self_reference {
+            definitions.extend(self.retrieve_self_reference_definitions());
         }
 
         definitions
     }
 
-    fn save_definitions(
+    pub fn save_project_definitions(
         &self,
-        kind: BlobDefinitionKind,
-        append: bool,
         definitions: Vec<String>,
-    ) -> Vec<BlobDefinition> {
-        let definitions_root = self.get_definitions_path();
+        tags: Vec<String>,
+        scope: Option<String>,
+    ) -> Result<Vec<BlobDefinition>> {
+        let author = self.definition_author();
 
-        create_dir_all(definitions_root.clone()).unwrap();
+        let definitions = definitions
+            .into_iter()
+            .map(|text| BlobDefinition::new(text, tags.clone(), scope.clone(), author.clone()))
+            .collect();
 
-        let file_path = format!("{}/{}", definitions_root, kind.as_filename().to_string());
-        // let mut file = File::create().unwrap();
+        self.append_definitions(BlobDefinitionKind::Project, definitions)
+    }
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .append(append)
-            .open(file_path)
-            .unwrap();
+    /// Import definitions, skipping the ones whose text is already defined.
+    pub fn import_project_definitions(
+        &self,
+        definitions: Vec<BlobDefinition>,
+    ) -> Result<Vec<BlobDefinition>> {
+        let existing: HashSet<String> = self
+            .retrieve_definitions(BlobDefinitionKind::Project)
+            .into_iter()
+            .map(|def| def.text)
+            .collect();
+
+        let definitions = definitions
+            .into_iter()
+            .filter(|def| !existing.contains(&def.text))
+            .collect();
+
+        self.append_definitions(BlobDefinitionKind::Project, definitions)
+    }
 
-        let now = Utc::now();
+    /// Find a project definition by id, any unambiguous prefix of the id is accepted.
+    pub fn find_project_definition(&self, id: &str) -> Result<BlobDefinition> {
+        let definitions = self.retrieve_definitions(BlobDefinitionKind::Project);
 
-        let mut final_definitions: Vec<BlobDefinition> = Vec::new();
+        let matches = definitions
+            .into_iter()
+            .filter(|def| def.id.starts_with(id))
+            .collect::<Vec<_>>();
 
-        for definition in definitions {
-            let def = BlobDefinition {
-                created_at: now,
-                definition: definition.clone(),
-            };
+        match matches.len() {
+            0 => Err(anyhow!("No definition with id {id}")),
+            1 => Ok(matches.into_iter().next().unwrap()),
+            _ => Err(anyhow!("The id {id} matches several definitions")),
+        }
+    }
+
+    pub fn remove_project_definition(&self, id: &str) -> Result<BlobDefinition> {
+        let removed = self.find_project_definition(id)?;
 
-            let new_line_definition =
-                format!("{}, {}\n", def.created_at.to_rfc3339(), def.definition);
+        let definitions = self
+            .retrieve_definitions(BlobDefinitionKind::Project)
+            .into_iter()
+            .filter(|def| def.id != removed.id)
+            .collect::<Vec<_>>();
 
-            file.write_all(new_line_definition.as_bytes()).unwrap();
+        self.write_definitions(&BlobDefinitionKind::Project, &definitions)?;
+
+        Ok(removed)
+    }
+
+    pub fn update_project_definition(&self, definition: BlobDefinition) -> Result<()> {
+        let definitions = self
+            .retrieve_definitions(BlobDefinitionKind::Project)
+            .into_iter()
+            .map(|def| match def.id == definition.id {
+                true => definition.clone(),
+                false => def,
+            })
+            .collect::<Vec<_>>();
+
+        self.write_definitions(&BlobDefinitionKind::Project, &definitions)
+    }
+}
 
-            final_definitions.push(def);
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A project folder with the given definitions files, removed when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let root = std::env::temp_dir().join(format!("blob-{name}-{}", std::process::id()));
+            fs::remove_dir_all(&root).ok();
+
+            let definitions = root.join(".blob/.definitions");
+            fs::create_dir_all(&definitions).unwrap();
+
+            for (file, contents) in files {
+                fs::write(definitions.join(file), contents).unwrap();
+            }
+
+            Fixture(root)
         }
 
-        final_definitions
+        fn processor(&self) -> BlobContextProcessor {
+            BlobContextProcessor::new(self.0.display().to_string())
+        }
+
+        fn read(&self, file: &str) -> Option<String> {
+            fs::read_to_string(self.0.join(".blob/.definitions").join(file)).ok()
+        }
     }
 
-    pub fn save_project_definitions(&self, definitions: Vec<String>) -> Vec<BlobDefinition> {
-        self.save_definitions(BlobDefinitionKind::Project, true, definitions)
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn legacy_definitions_are_only_migrated_by_a_write() {
+        let fixture = Fixture::new(
+            "legacy",
+            &[("_project.md", "2023-01-01T00:00:00Z, tabs, not spaces\n")],
+        );
+        let processor = fixture.processor();
+
+        let read = processor.retrieve_definitions(BlobDefinitionKind::Project);
+
+        assert_eq!(read[0].text, "tabs, not spaces");
+        assert!(fixture.read("project.jsonl").is_none());
+        assert!(fixture.read("_project.md").is_some());
+
+        processor
+            .save_project_definitions(vec!["new".to_string()], vec![], None)
+            .unwrap();
+
+        let migrated = processor.retrieve_definitions(BlobDefinitionKind::Project);
+
+        assert_eq!(migrated[0], read[0]);
+        assert_eq!(migrated[1].text, "new");
+        assert!(fixture.read("_project.md").is_none());
+        assert!(fixture.read("_project.md.bak").is_some());
+    }
+
+    #[test]
+    fn unparsed_lines_survive_a_rewrite() {
+        let kept = BlobDefinition::new("kept".to_string(), vec![], None, None);
+        let removed = BlobDefinition::new("removed".to_string(), vec![], None, None);
+
+        let contents = format!(
+            "{}\n{{\"id\": hand edit gone wrong\n{}\n",
+            serde_json::to_string(&removed).unwrap(),
+            serde_json::to_string(&kept).unwrap()
+        );
+
+        let fixture = Fixture::new("unparsed", &[("project.jsonl", &contents)]);
+        let processor = fixture.processor();
+
+        processor.remove_project_definition(&removed.id).unwrap();
+
+        let mut edited = kept.clone();
+        edited.text = "edited".to_string();
+        processor.update_project_definition(edited.clone()).unwrap();
+
+        assert_eq!(
+            fixture.read("project.jsonl").unwrap(),
+            format!(
+                "{{\"id\": hand edit gone wrong\n{}\n",
+                serde_json::to_string(&edited).unwrap()
+            )
+        );
     }
 }
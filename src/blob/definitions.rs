@@ -0,0 +1,365 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use globset::GlobBuilder;
+use serde_derive::{Deserialize, Serialize};
+
+/// A piece of project knowledge given to the model as context.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlobDefinition {
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl BlobDefinition {
+    pub fn new(
+        text: String,
+        tags: Vec<String>,
+        scope: Option<String>,
+        author: Option<String>,
+    ) -> Self {
+        let created_at = Utc::now();
+
+        BlobDefinition {
+            id: definition_id(&text, &created_at, 0),
+            text,
+            tags,
+            scope: scope.map(|scope| normalize_scope(&scope)),
+            author,
+            created_at,
+        }
+    }
+
+    /// Make the id unique among `taken`, rehashing with a salt on collision.
+    pub fn ensure_unique_id(&mut self, taken: &HashSet<String>) {
+        let mut salt = 0;
+
+        while self.id.is_empty() || taken.contains(&self.id) {
+            salt += 1;
+            self.id = definition_id(&self.text, &self.created_at, salt);
+        }
+    }
+
+    /// Whether the definition applies to `path`, relative to the project root.
+    ///
+    /// Unscoped definitions apply everywhere. A scope is either a directory or file
+    /// (`src/codex`) or a glob (`src/**/*.rs`), globs without a `/` match at any depth.
+    pub fn applies_to(&self, path: &str) -> bool {
+        let scope = match &self.scope {
+            Some(scope) => normalize_scope(scope),
+            None => return true,
+        };
+
+        let path = path.trim_start_matches("./");
+
+        if !is_glob(&scope) {
+            return path == scope || path.starts_with(&format!("{scope}/"));
+        }
+
+        let pattern = match scope.contains('/') {
+            true => scope,
+            false => format!("**/{scope}"),
+        };
+
+        GlobBuilder::new(&pattern)
+            .literal_separator(true)
+            .build()
+            .map(|glob| glob.compile_matcher().is_match(path))
+            .unwrap_or(false)
+    }
+
+    /// Literal path components and characters of the scope, unscoped definitions are
+    /// the least specific.
+    fn specificity(&self) -> (usize, usize) {
+        let scope = match &self.scope {
+            Some(scope) => normalize_scope(scope),
+            None => return (0, 0),
+        };
+
+        let literal = match scope.find(['*', '?', '[', '{']) {
+            Some(index) => &scope[..index],
+            None => &scope,
+        };
+
+        (
+            literal.split('/').filter(|part| !part.is_empty()).count() + 1,
+            literal.len(),
+        )
+    }
+
+    pub fn render(&self) -> String {
+        let mut line = format!("{}  {}", self.id, self.text);
+
+        if !self.tags.is_empty() {
+            line.push_str(&format!("  [{}]", self.tags.join(", ")));
+        }
+
+        if let Some(scope) = &self.scope {
+            line.push_str(&format!("  ({scope})"));
+        }
+
+        line
+    }
+}
+
+fn normalize_scope(scope: &str) -> String {
+    scope
+        .trim()
+        .trim_start_matches("./")
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn is_glob(scope: &str) -> bool {
+    scope.contains(['*', '?', '[', '{'])
+}
+
+/// Definitions that apply to `path`, from the least to the most specific so the
+/// closest ones end up next to the instruction. Without a path only the unscoped
+/// definitions apply.
+pub fn applicable_definitions<'a>(
+    definitions: &'a [BlobDefinition],
+    path: Option<&str>,
+) -> Vec<&'a BlobDefinition> {
+    let mut applicable = definitions
+        .iter()
+        .filter(|definition| match path {
+            Some(path) => definition.applies_to(path),
+            None => definition.scope.is_none(),
+        })
+        .collect::<Vec<_>>();
+
+    applicable.sort_by_key(|definition| definition.specificity());
+
+    applicable
+}
+
+/// Definitions that apply to at least one of `paths`, for changes spanning several
+/// files. The unscoped ones always apply, ordered like [`applicable_definitions`].
+pub fn applicable_definitions_for_paths<'a>(
+    definitions: &'a [BlobDefinition],
+    paths: &[String],
+) -> Vec<&'a BlobDefinition> {
+    let mut applicable = definitions
+        .iter()
+        .filter(|definition| {
+            definition.scope.is_none() || paths.iter().any(|path| definition.applies_to(path))
+        })
+        .collect::<Vec<_>>();
+
+    applicable.sort_by_key(|definition| definition.specificity());
+
+    applicable
+}
+
+/// Texts of the definitions that apply to `path`, see [`applicable_definitions`].
+pub fn context_lines_for(definitions: &[BlobDefinition], path: Option<&str>) -> Vec<String> {
+    applicable_definitions(definitions, path)
+        .into_iter()
+        .map(|definition| definition.text.clone())
+        .collect()
+}
+
+/// Short FNV-1a hash of the definition, 8 hex chars are plenty for a single project.
+fn definition_id(text: &str, created_at: &DateTime<Utc>, salt: u32) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    let seed = format!("{}\n{}\n{}", created_at.to_rfc3339(), salt, text);
+
+    for byte in seed.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    format!("{:016x}", hash)[..8].to_string()
+}
+
+const SELF_REFERENCE: &str = include_str!("self_reference.md");
+
+/// Definitions describing blob, shipped with the binary so they match its commands.
+pub fn builtin_self_reference_definitions() -> Vec<BlobDefinition> {
+    // Lines have no timestamp, the epoch keeps their ids stable between releases.
+    parse_legacy_definitions(SELF_REFERENCE, DateTime::<Utc>::from(std::time::UNIX_EPOCH))
+}
+
+/// Parse the legacy `<rfc3339>, <definition>` lines of `_project.md`.
+///
+/// Only the first comma separates the timestamp, so definitions containing commas
+/// survive. Lines with an invalid timestamp are kept whole with `fallback` as date.
+pub fn parse_legacy_definitions(contents: &str, fallback: DateTime<Utc>) -> Vec<BlobDefinition> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (created_at, text) = match line.split_once(',') {
+                Some((created_at, text)) => match DateTime::parse_from_rfc3339(created_at.trim()) {
+                    Ok(created_at) => (created_at.with_timezone(&Utc), text.trim()),
+                    Err(_) => (fallback, line.trim()),
+                },
+                None => (fallback, line.trim()),
+            };
+
+            BlobDefinition {
+                id: definition_id(text, &created_at, 0),
+                text: text.to_string(),
+                tags: vec![],
+                scope: None,
+                author: None,
+                created_at,
+            }
+        })
+        .collect()
+}
+
+/// Parse a file given to `blob define import`.
+///
+/// JSONL records (as written by blob) are taken as is, any other non-empty line that
+/// isn't a `#` comment becomes a new definition.
+pub fn parse_import(contents: &str, author: Option<String>) -> Vec<BlobDefinition> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match serde_json::from_str::<BlobDefinition>(line) {
+            Ok(definition) => definition,
+            Err(_) => BlobDefinition::new(line.to_string(), vec![], None, author.clone()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn scoped(text: &str, scope: Option<&str>) -> BlobDefinition {
+        BlobDefinition::new(text.to_string(), vec![], scope.map(str::to_string), None)
+    }
+
+    #[test]
+    fn legacy_lines_split_on_the_first_comma_only() {
+        let fallback = date("2020-01-01T00:00:00Z");
+
+        let definitions = parse_legacy_definitions(
+            "2023-02-14T15:30:12+00:00, errors are returned, never unwrapped, in the cli\n\
+             \n\
+             not a date, so the whole line is kept\n\
+             no comma at all",
+            fallback,
+        );
+
+        let parsed = definitions
+            .iter()
+            .map(|definition| (definition.created_at, definition.text.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            parsed,
+            [
+                (
+                    date("2023-02-14T15:30:12Z"),
+                    "errors are returned, never unwrapped, in the cli"
+                ),
+                (fallback, "not a date, so the whole line is kept"),
+                (fallback, "no comma at all"),
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_ids_are_stable() {
+        let contents = "2023-02-14T15:30:12Z, uses tabs";
+        let fallback = date("2020-01-01T00:00:00Z");
+
+        assert_eq!(
+            parse_legacy_definitions(contents, fallback)[0].id,
+            parse_legacy_definitions(contents, Utc::now())[0].id
+        );
+    }
+
+    #[test]
+    fn jsonl_records_round_trip_and_plain_lines_become_definitions() {
+        let mut definition = scoped("the api is versioned, under /v1", Some("./src/api/"));
+        definition.tags = vec!["api".to_string()];
+
+        let contents = format!(
+            "# exported definitions\n{}\n\nplain text, with a comma\n",
+            serde_json::to_string(&definition).unwrap()
+        );
+
+        let imported = parse_import(&contents, Some("ana".to_string()));
+
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0], definition);
+        assert_eq!(imported[0].scope.as_deref(), Some("src/api"));
+        assert_eq!(imported[1].text, "plain text, with a comma");
+        assert_eq!(imported[1].author.as_deref(), Some("ana"));
+    }
+
+    #[test]
+    fn scopes_match_folders_files_and_globs() {
+        let folder = scoped("folder", Some("src/codex"));
+        let glob = scoped("glob", Some("src/**/*.rs"));
+        let anywhere = scoped("anywhere", Some("*.md"));
+
+        assert!(folder.applies_to("src/codex/processor.rs"));
+        assert!(folder.applies_to("./src/codex"));
+        assert!(!folder.applies_to("src/codex_old/processor.rs"));
+
+        assert!(glob.applies_to("src/blob/context.rs"));
+        assert!(!glob.applies_to("tests/context.rs"));
+
+        assert!(anywhere.applies_to("docs/guide/README.md"));
+        assert!(scoped("unscoped", None).applies_to("anything"));
+    }
+
+    #[test]
+    fn the_most_specific_definitions_come_last() {
+        let definitions = [
+            scoped("file", Some("src/blob/context.rs")),
+            scoped("unscoped", None),
+            scoped("other", Some("tests")),
+            scoped("folder", Some("src/blob")),
+            scoped("glob", Some("src/**/*.rs")),
+        ];
+
+        assert_eq!(
+            context_lines_for(&definitions, Some("src/blob/context.rs")),
+            ["unscoped", "glob", "folder", "file"]
+        );
+        assert_eq!(context_lines_for(&definitions, None), ["unscoped"]);
+
+        let paths = ["tests/a.rs".to_string(), "src/blob/mod.rs".to_string()];
+
+        assert_eq!(
+            applicable_definitions_for_paths(&definitions, &paths)
+                .into_iter()
+                .map(|definition| definition.text.as_str())
+                .collect::<Vec<_>>(),
+            ["unscoped", "glob", "other", "folder"]
+        );
+    }
+
+    #[test]
+    fn colliding_ids_are_rehashed() {
+        let mut definition = scoped("same text", None);
+        let taken = HashSet::from([definition.id.clone()]);
+
+        definition.ensure_unique_id(&taken);
+
+        assert!(!taken.contains(&definition.id));
+        assert_eq!(definition.id.len(), 8);
+    }
+}
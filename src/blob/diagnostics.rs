@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use serde_json::Value;
+
+/// A compiler or linter message anchored to a file position.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    #[serde(default)]
+    pub column: Option<usize>,
+    pub level: String,
+    pub message: String,
+    pub rendered: Option<String>,
+}
+
+impl Diagnostic {
+    /// Parse a `rustc` JSON diagnostic, either bare (`--error-format=json`) or
+    /// wrapped in a cargo `compiler-message`.
+    fn from_json(value: &Value) -> Option<Self> {
+        let message = match value.get("reason").and_then(Value::as_str) {
+            Some("compiler-message") => value.get("message")?,
+            Some(_) => return None,
+            None => value,
+        };
+
+        let level = message.get("level")?.as_str()?;
+
+        if level == "failure-note" {
+            return None;
+        }
+
+        let spans = message.get("spans")?.as_array()?;
+        let span = spans
+            .iter()
+            .find(|span| span.get("is_primary").and_then(Value::as_bool) == Some(true))
+            .or_else(|| spans.first())?;
+
+        Some(Diagnostic {
+            file: span.get("file_name")?.as_str()?.to_string(),
+            line: span.get("line_start")?.as_u64()? as usize,
+            column: span
+                .get("column_start")
+                .and_then(Value::as_u64)
+                .map(|column| column as usize),
+            level: level.to_string(),
+            message: message.get("message")?.as_str()?.to_string(),
+            rendered: message
+                .get("rendered")
+                .and_then(Value::as_str)
+                .map(|rendered| rendered.to_string()),
+        })
+    }
+
+    /// Parse the common `file:line:col: message` format (gcc, tsc, eslint unix, ruff...),
+    /// the column is optional as in `file:line: message` (mypy, shellcheck gcc...).
+    fn from_line(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(3, ':');
+
+        let file = parts.next()?.trim();
+        let line_number = parts.next()?.trim().parse::<usize>().ok()?;
+        let rest = parts.next()?;
+
+        let (column, message) = match rest.split_once(':') {
+            Some((column, message)) => match column.trim().parse::<usize>() {
+                Ok(column) => (Some(column), message.trim()),
+                Err(_) => (None, rest.trim()),
+            },
+            None => (None, rest.trim()),
+        };
+
+        if file.is_empty() || message.is_empty() || file.contains(char::is_whitespace) {
+            return None;
+        }
+
+        let lowercase = message.to_lowercase();
+
+        let level = if lowercase.starts_with("warning") {
+            "warning"
+        } else if lowercase.starts_with("note") {
+            "note"
+        } else {
+            "error"
+        };
+
+        Some(Diagnostic {
+            file: file.to_string(),
+            line: line_number,
+            column,
+            level: level.to_string(),
+            message: message.to_string(),
+            rendered: None,
+        })
+    }
+
+    pub fn render(&self) -> String {
+        match &self.rendered {
+            Some(rendered) => rendered.trim_end().to_string(),
+            None => match self.column {
+                Some(column) => format!("{}:{}:{}: {}", self.file, self.line, column, self.message),
+                None => format!("{}:{}: {}", self.file, self.line, self.message),
+            },
+        }
+    }
+
+    /// Path of the diagnosed file when it exists inside `project_root`, tools also
+    /// report files of dependencies or files that have since been removed.
+    pub fn project_file(&self, project_root: &str) -> Option<PathBuf> {
+        let root = Path::new(project_root).canonicalize().ok()?;
+        let file = Path::new(project_root)
+            .join(&self.file)
+            .canonicalize()
+            .ok()?;
+
+        (file.is_file() && file.starts_with(&root)).then_some(file)
+    }
+}
+
+/// Extract every diagnostic from the output of a build or lint command.
+pub fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        let diagnostic = if line.starts_with('{') {
+            serde_json::from_str::<Value>(line)
+                .ok()
+                .and_then(|value| Diagnostic::from_json(&value))
+        } else {
+            Diagnostic::from_line(line)
+        };
+
+        if let Some(diagnostic) = diagnostic {
+            if !diagnostics.contains(&diagnostic) {
+                diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    diagnostics
+}
+
+pub fn group_by_file(diagnostics: Vec<Diagnostic>) -> BTreeMap<String, Vec<Diagnostic>> {
+    let mut groups: BTreeMap<String, Vec<Diagnostic>> = BTreeMap::new();
+
+    for diagnostic in diagnostics {
+        groups
+            .entry(diagnostic.file.clone())
+            .or_default()
+            .push(diagnostic);
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cargo_messages_use_the_primary_span() {
+        let output = r#"{"reason":"compiler-artifact","package_id":"blob 0.1.0"}
+{"reason":"compiler-message","message":{"level":"error","message":"mismatched types","rendered":"error[E0308]: mismatched types\n","spans":[{"file_name":"src/other.rs","line_start":1,"column_start":1,"is_primary":false},{"file_name":"src/main.rs","line_start":12,"column_start":5,"is_primary":true}]}}
+{"reason":"build-finished","success":false}"#;
+
+        assert_eq!(
+            parse_diagnostics(output),
+            [Diagnostic {
+                file: "src/main.rs".to_string(),
+                line: 12,
+                column: Some(5),
+                level: "error".to_string(),
+                message: "mismatched types".to_string(),
+                rendered: Some("error[E0308]: mismatched types\n".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn bare_rustc_messages_are_parsed_and_failure_notes_skipped() {
+        let output = r#"{"level":"warning","message":"unused variable: `x`","spans":[{"file_name":"src/lib.rs","line_start":3,"column_start":9}]}
+{"level":"failure-note","message":"For more information about this error, try `rustc --explain E0308`.","spans":[]}"#;
+
+        let diagnostics = parse_diagnostics(output);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file, "src/lib.rs");
+        assert_eq!(diagnostics[0].level, "warning");
+        assert_eq!(
+            diagnostics[0].render(),
+            "src/lib.rs:3:9: unused variable: `x`"
+        );
+    }
+
+    #[test]
+    fn line_diagnostics_with_and_without_a_column() {
+        let output = "src/app.ts:4:10: error TS2322: Type 'string' is not assignable\n\
+                      app/models.py:27: note: By default the bodies of untyped functions are not checked\n\
+                      lib/util.c:8:1: warning: implicit declaration of function 'foo'";
+
+        let diagnostics = parse_diagnostics(output);
+
+        let positions = diagnostics
+            .iter()
+            .map(|d| (d.file.as_str(), d.line, d.column, d.level.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            positions,
+            [
+                ("src/app.ts", 4, Some(10), "error"),
+                ("app/models.py", 27, None, "note"),
+                ("lib/util.c", 8, Some(1), "warning"),
+            ]
+        );
+        assert_eq!(
+            diagnostics[1].render(),
+            "app/models.py:27: note: By default the bodies of untyped functions are not checked"
+        );
+    }
+
+    #[test]
+    fn other_output_lines_are_ignored() {
+        let output = "   Compiling blob v0.1.0 (/root/blob)\n\
+                      error: could not compile `blob` due to 2 previous errors\n\
+                      \x20 --> src/main.rs:3:5\n\
+                      see https://example.com:443/docs\n\
+                      Found 2 errors in 1 file";
+
+        assert_eq!(parse_diagnostics(output), []);
+    }
+
+    #[test]
+    fn repeated_diagnostics_are_kept_once_and_grouped_by_file() {
+        let output =
+            "b.py:1:1: E1 first\na.py:2:1: E2 second\nb.py:1:1: E1 first\nb.py:5:3: E3 third";
+
+        let groups = group_by_file(parse_diagnostics(output));
+
+        assert_eq!(groups.keys().collect::<Vec<_>>(), ["a.py", "b.py"]);
+        assert_eq!(groups["b.py"].len(), 2);
+    }
+}
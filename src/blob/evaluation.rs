@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+
+use crate::representation::tree::formats::TreeFormat;
+
+/// How well one proposed structure, written in the format it was given, reads back.
+pub struct ProposalCheck {
+    /// The proposal could be parsed in its format.
+    pub parsed: bool,
+    /// The proposal differs from the current structure, i.e. the instruction was applied.
+    pub changed: bool,
+    /// Share of the current files still in the proposal.
+    pub kept: f64,
+    /// Share of the expected paths found in the proposal, when some were given.
+    pub expected: Option<f64>,
+}
+
+impl ProposalCheck {
+    /// `current` are the paths of the project before the instruction, folders end with `/`.
+    pub fn new(current: &[String], proposal: Option<Vec<String>>, expected: &[String]) -> Self {
+        let proposal = match proposal {
+            Some(proposal) => proposal,
+            None => {
+                return ProposalCheck {
+                    parsed: false,
+                    changed: false,
+                    kept: 0.0,
+                    expected: (!expected.is_empty()).then_some(0.0),
+                }
+            }
+        };
+
+        let proposed = proposal
+            .iter()
+            .map(|path| path.trim_end_matches('/'))
+            .collect::<HashSet<_>>();
+
+        let changed = proposed
+            != current
+                .iter()
+                .map(|path| path.trim_end_matches('/'))
+                .collect::<HashSet<_>>();
+
+        let current_files = current
+            .iter()
+            .filter(|path| !path.ends_with('/'))
+            .collect::<Vec<_>>();
+
+        let kept = share(
+            current_files
+                .iter()
+                .filter(|path| proposed.contains(path.as_str()))
+                .count(),
+            current_files.len(),
+        );
+
+        let expected = (!expected.is_empty()).then(|| {
+            share(
+                expected
+                    .iter()
+                    .filter(|path| proposed.contains(path.trim_end_matches('/')))
+                    .count(),
+                expected.len(),
+            )
+        });
+
+        ProposalCheck {
+            parsed: true,
+            changed,
+            kept,
+            expected,
+        }
+    }
+
+    /// 0 when the proposal can't be read back or gives the current structure back
+    /// unchanged, which would otherwise keep every file, else the mean of `kept` and
+    /// `expected`.
+    pub fn score(&self) -> f64 {
+        match (self.parsed && self.changed, self.expected) {
+            (false, _) => 0.0,
+            (true, Some(expected)) => (self.kept + expected) / 2.0,
+            (true, None) => self.kept,
+        }
+    }
+}
+
+fn share(count: usize, total: usize) -> f64 {
+    match total {
+        0 => 1.0,
+        total => count as f64 / total as f64,
+    }
+}
+
+/// Proposals of the model for one representation, see `blob eval-repr`.
+pub struct RepresentationEvaluation {
+    pub format: TreeFormat,
+    /// Estimated tokens of the current structure in this format.
+    pub tokens: usize,
+    pub checks: Vec<ProposalCheck>,
+}
+
+impl RepresentationEvaluation {
+    pub fn score(&self) -> f64 {
+        mean(self.checks.iter().map(ProposalCheck::score))
+    }
+
+    fn parsed(&self) -> usize {
+        self.checks.iter().filter(|check| check.parsed).count()
+    }
+
+    fn changed(&self) -> usize {
+        self.checks.iter().filter(|check| check.changed).count()
+    }
+
+    fn kept(&self) -> f64 {
+        mean(self.checks.iter().map(|check| check.kept))
+    }
+
+    fn expected(&self) -> Option<f64> {
+        let found = self
+            .checks
+            .iter()
+            .filter_map(|check| check.expected)
+            .collect::<Vec<_>>();
+
+        (!found.is_empty()).then(|| mean(found.into_iter()))
+    }
+}
+
+fn mean<I: Iterator<Item = f64>>(values: I) -> f64 {
+    let values = values.collect::<Vec<_>>();
+
+    match values.len() {
+        0 => 0.0,
+        len => values.iter().sum::<f64>() / len as f64,
+    }
+}
+
+/// Table of the evaluated representations, the best score first.
+pub fn render_evaluation(model: &str, evaluations: &mut [RepresentationEvaluation]) -> String {
+    evaluations.sort_by(|a, b| b.score().total_cmp(&a.score()));
+
+    let mut lines = vec![
+        format!("Representations evaluated with {model}:"),
+        format!(
+            "  {:<8} {:>7} {:>7} {:>7} {:>6} {:>9} {:>6}",
+            "format", "tokens", "parsed", "changed", "kept", "expected", "score"
+        ),
+    ];
+
+    for evaluation in evaluations.iter() {
+        lines.push(format!(
+            "  {:<8} {:>7} {:>7} {:>7} {:>5.0}% {:>9} {:>6.2}",
+            evaluation.format.to_string(),
+            evaluation.tokens,
+            format!("{}/{}", evaluation.parsed(), evaluation.checks.len()),
+            format!("{}/{}", evaluation.changed(), evaluation.checks.len()),
+            evaluation.kept() * 100.0,
+            evaluation
+                .expected()
+                .map(|expected| format!("{:.0}%", expected * 100.0))
+                .unwrap_or("-".to_string()),
+            evaluation.score()
+        ));
+    }
+
+    if let Some(best) = evaluations.first() {
+        lines.push(String::new());
+        lines.push(format!(
+            "Best: {}. Use it with `--repr {}` or in .blob/config.toml:",
+            best.format, best.format
+        ));
+        lines.push(format!(
+            "  [representation.models]\n  \"{}\" = \"{}\"",
+            model, best.format
+        ));
+    }
+
+    lines.join("\n")
+}
@@ -1,4 +1,15 @@
 pub mod analysis;
+pub mod config;
 pub mod context;
+pub mod definitions;
+pub mod diagnostics;
+pub mod evaluation;
 pub mod mutation;
+pub mod plan;
+pub mod question;
+pub mod ranking;
+pub mod redaction;
+pub mod review;
+pub mod verification;
+pub mod workspace;
 // pub mod self;
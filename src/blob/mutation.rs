@@ -1,16 +1,27 @@
 use chrono::{DateTime, Utc};
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
-use std::{path::Path, process::Command};
+use std::{fmt, process::Command};
 
-use crate::representation::{
-    tree::filters::{FilterAggregate, GitignoreFilter},
-    tree::iterator::TreeIter,
-};
+use crate::blob::workspace::structure_tree_iter;
+use crate::representation::tree::formats::TreeFormat;
+use crate::representation::tree::iterator::TreeIter;
 
 #[derive(Clone, Debug)]
 pub struct MutationError(String);
 
+impl MutationError {
+    pub fn new(message: String) -> Self {
+        MutationError(message)
+    }
+}
+
+impl fmt::Display for MutationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProjectMutationDraft {
     pub path_root: String,
@@ -26,6 +37,9 @@ pub struct ProjectMutationProposed {
     pub parent: Box<ProjectMutationDraft>,
     current_structure: String,
     proposed_structure: String,
+    /// Representation both structures are written in.
+    #[serde(default)]
+    pub format: TreeFormat,
     // state: MutationState,
 }
 
@@ -41,6 +55,13 @@ pub struct SourceFileMutationDraft {
     pub file_path: String,
     pub prompt: String,
 
+    #[serde(default)]
+    pub context_lines: Vec<String>,
+
+    /// Whether a missing file may be created instead of refusing the mutation.
+    #[serde(default)]
+    pub create: bool,
+
     pub created_at: DateTime<Utc>,
 }
 
@@ -51,6 +72,36 @@ pub struct SourceFileMutation {
     pub proposed_content: String,
 }
 
+/// Several file edits that are reviewed and applied as one mutation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceFileMutationGroup {
+    pub prompt: String,
+    pub mutations: Vec<SourceFileMutation>,
+
+    pub created_at: DateTime<Utc>,
+}
+
+/// Saved stage a project mutation is resumed from, see `blob resume`.
+pub enum ProjectMutationStage {
+    Drafted(Box<ProjectMutationDraft>),
+    Proposed(Box<ProjectMutationProposed>),
+}
+
+impl ProjectMutationStage {
+    /// Go back to the draft, so the structure is proposed again.
+    pub fn into_draft(self) -> Box<ProjectMutationDraft> {
+        match self {
+            ProjectMutationStage::Drafted(draft) => draft,
+            ProjectMutationStage::Proposed(proposal) => proposal.parent,
+        }
+    }
+}
+
+/// Mutations are stored and referenced by their creation time, e.g. `20230214153012`.
+pub fn mutation_id(created_at: &DateTime<Utc>) -> String {
+    created_at.format("%Y%m%d%H%M%S").to_string()
+}
+
 impl ProjectMutationDraft {
     pub fn new(path_root: String, prompt: String, context_lines: Vec<String>) -> Self {
         ProjectMutationDraft {
@@ -61,19 +112,11 @@ impl ProjectMutationDraft {
         }
     }
 
-    fn calculate_tree_iter(&self) -> Box<TreeIter> {
-        let mut filters = FilterAggregate::default();
-
-        let root = Path::new(&self.path_root).to_owned();
-
-        let github_filter = GitignoreFilter::new(root.clone()).unwrap().unwrap();
-
-        filters.push(github_filter);
-
-        Box::new(TreeIter::new(root, filters).unwrap())
+    fn calculate_tree_iter(&self) -> anyhow::Result<Box<TreeIter>> {
+        structure_tree_iter(&self.path_root, &self.prompt)
     }
 
-    pub fn tree_iter(&mut self) -> Box<TreeIter> {
+    pub fn tree_iter(&mut self) -> anyhow::Result<Box<TreeIter>> {
         self.calculate_tree_iter()
     }
 }
@@ -83,14 +126,29 @@ impl ProjectMutationProposed {
         parent: Box<ProjectMutationDraft>,
         current_structure: String,
         proposed_structure: String,
+        format: TreeFormat,
     ) -> Self {
         Self {
             parent,
             current_structure,
             proposed_structure,
+            format,
         }
     }
 
+    pub fn current_structure(&self) -> &str {
+        &self.current_structure
+    }
+
+    pub fn proposed_structure(&self) -> &str {
+        &self.proposed_structure
+    }
+
+    /// Replace the proposal of the model, e.g. with a structure corrected by hand.
+    pub fn set_proposed_structure(&mut self, proposed_structure: String) {
+        self.proposed_structure = proposed_structure;
+    }
+
     pub fn generate_prompt(self) -> Result<String, MutationError> {
         let pwd_command = Command::new("pwd").output().unwrap();
         let pwd_result = String::from_utf8_lossy(&pwd_command.stdout);
@@ -163,9 +221,16 @@ impl SourceFileMutationDraft {
         SourceFileMutationDraft {
             file_path,
             prompt,
+            context_lines: vec![],
+            create: false,
             created_at: Utc::now(),
         }
     }
+
+    pub fn with_creation(mut self, create: bool) -> Self {
+        self.create = create;
+        self
+    }
 }
 
 impl SourceFileMutation {
@@ -181,3 +246,13 @@ impl SourceFileMutation {
         }
     }
 }
+
+impl SourceFileMutationGroup {
+    pub fn new(prompt: String, mutations: Vec<SourceFileMutation>) -> Self {
+        Self {
+            prompt,
+            mutations,
+            created_at: Utc::now(),
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::blob::mutation::{
+    mutation_id, MutationError, ProjectMutationDraft, SourceFileMutationDraft,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MutationPlanStepKind {
+    Structure,
+    SourceFile { file_path: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MutationPlanStepStatus {
+    Pending,
+    Applied,
+    Discarded,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MutationPlanStep {
+    pub index: usize,
+    pub kind: MutationPlanStepKind,
+    pub instruction: String,
+    pub status: MutationPlanStepStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MutationPlan {
+    pub parent: Box<ProjectMutationDraft>,
+    pub steps: Vec<MutationPlanStep>,
+}
+
+impl MutationPlanStep {
+    /// Parse a single plan line like `2. [file src/main.rs] add a --verbose flag`.
+    fn parse(index: usize, line: &str) -> Option<Self> {
+        let line = line.trim();
+        let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+
+        if rest.len() == line.len() {
+            return None;
+        }
+
+        let rest = rest
+            .strip_prefix('.')
+            .or_else(|| rest.strip_prefix(')'))?
+            .trim_start();
+
+        let (tag, instruction) = rest.strip_prefix('[')?.split_once(']')?;
+        let instruction = instruction.trim();
+
+        if instruction.is_empty() {
+            return None;
+        }
+
+        let tag = tag.trim();
+
+        let kind = if tag.eq_ignore_ascii_case("structure") {
+            MutationPlanStepKind::Structure
+        } else {
+            let (name, file_path) = tag.split_once(char::is_whitespace)?;
+
+            if !name.eq_ignore_ascii_case("file") {
+                return None;
+            }
+
+            MutationPlanStepKind::SourceFile {
+                file_path: file_path.trim().to_string(),
+            }
+        };
+
+        Some(MutationPlanStep {
+            index,
+            kind,
+            instruction: instruction.to_string(),
+            status: MutationPlanStepStatus::Pending,
+        })
+    }
+
+    /// Id of the mutation produced by this step, nested under the plan.
+    pub fn mutation_id(&self, plan: &MutationPlan) -> String {
+        format!("{}/{:02}", plan.id(), self.index)
+    }
+
+    pub fn render(&self) -> String {
+        match &self.kind {
+            MutationPlanStepKind::Structure => {
+                format!("{}. [structure] {}", self.index, self.instruction)
+            }
+            MutationPlanStepKind::SourceFile { file_path } => {
+                format!("{}. [file {}] {}", self.index, file_path, self.instruction)
+            }
+        }
+    }
+
+    pub fn project_mutation_draft(&self, plan: &MutationPlan) -> ProjectMutationDraft {
+        ProjectMutationDraft::new(
+            plan.parent.path_root.clone(),
+            self.instruction.clone(),
+            plan.parent.context_lines.clone().unwrap_or_default(),
+        )
+    }
+
+    pub fn source_file_mutation_draft(
+        &self,
+        plan: &MutationPlan,
+    ) -> Option<SourceFileMutationDraft> {
+        match &self.kind {
+            // The approved plan names the file, so it may be one the plan creates.
+            MutationPlanStepKind::SourceFile { file_path } => Some(
+                SourceFileMutationDraft::new(
+                    format!("{}/{}", plan.parent.path_root, file_path),
+                    self.instruction.clone(),
+                )
+                .with_creation(true),
+            ),
+            MutationPlanStepKind::Structure => None,
+        }
+    }
+}
+
+impl MutationPlan {
+    pub fn new_from_parent(
+        parent: Box<ProjectMutationDraft>,
+        steps: Vec<MutationPlanStep>,
+    ) -> Self {
+        Self { parent, steps }
+    }
+
+    pub fn id(&self) -> String {
+        mutation_id(&self.parent.created_at)
+    }
+
+    /// Parse a numbered plan, ignoring blank lines and `#` comments.
+    /// Steps are renumbered in the order they appear.
+    pub fn parse_steps(plan: &str) -> Result<Vec<MutationPlanStep>, MutationError> {
+        let mut steps = Vec::new();
+
+        for line in plan.lines() {
+            let trimmed = line.trim();
+
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            match MutationPlanStep::parse(steps.len() + 1, trimmed) {
+                Some(step) => steps.push(step),
+                None => {
+                    return Err(MutationError::new(format!(
+                        "Malformed plan step: '{trimmed}'"
+                    )))
+                }
+            }
+        }
+
+        if steps.is_empty() {
+            return Err(MutationError::new("The plan has no steps".to_string()));
+        }
+
+        Ok(steps)
+    }
+
+    pub fn render(&self) -> String {
+        self.steps
+            .iter()
+            .map(|step| step.render())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Text opened in the editor so the user can rework the plan.
+    pub fn render_for_editing(&self) -> String {
+        format!(
+            "# Plan for: {}\n\
+             # One step per line, `N. [structure] <instruction>` or `N. [file <path>] <instruction>`.\n\
+             # File paths are relative to the project root. Lines starting with '#' are ignored.\n\
+             {}\n",
+            self.parent.prompt.trim().replace('\n', " "),
+            self.render(),
+        )
+    }
+
+    pub fn set_step_status(&mut self, index: usize, status: MutationPlanStepStatus) {
+        if let Some(step) = self.steps.iter_mut().find(|step| step.index == index) {
+            step.status = status;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(steps: &str) -> MutationPlan {
+        let parent =
+            ProjectMutationDraft::new("/project".to_string(), "add a cli".to_string(), vec![]);
+
+        MutationPlan::new_from_parent(Box::new(parent), MutationPlan::parse_steps(steps).unwrap())
+    }
+
+    #[test]
+    fn steps_are_parsed_and_renumbered() {
+        let steps = MutationPlan::parse_steps(
+            "# Plan for: add a cli\n\
+             \n\
+             1. [structure] create src/cli\n\
+             3) [File src/cli/args.rs] parse the arguments, with clap\n\
+             7. [file src/main.rs]   call the parser  ",
+        )
+        .unwrap();
+
+        let parsed = steps
+            .iter()
+            .map(|step| (step.index, step.kind.clone(), step.instruction.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            parsed,
+            [
+                (1, MutationPlanStepKind::Structure, "create src/cli"),
+                (
+                    2,
+                    MutationPlanStepKind::SourceFile {
+                        file_path: "src/cli/args.rs".to_string()
+                    },
+                    "parse the arguments, with clap"
+                ),
+                (
+                    3,
+                    MutationPlanStepKind::SourceFile {
+                        file_path: "src/main.rs".to_string()
+                    },
+                    "call the parser"
+                ),
+            ]
+        );
+        assert!(steps
+            .iter()
+            .all(|step| step.status == MutationPlanStepStatus::Pending));
+    }
+
+    #[test]
+    fn malformed_steps_are_rejected() {
+        for line in [
+            "create src/cli",
+            "1 [structure] no separator",
+            "1. structure without brackets",
+            "1. [file] no path",
+            "1. [folder src] unknown kind",
+            "1. [structure]",
+        ] {
+            let err = MutationPlan::parse_steps(line).unwrap_err();
+
+            assert!(
+                err.to_string().starts_with("Malformed plan step"),
+                "{line}: {err}"
+            );
+        }
+
+        let err = MutationPlan::parse_steps("# only a comment\n\n").unwrap_err();
+
+        assert_eq!(err.to_string(), "The plan has no steps");
+    }
+
+    #[test]
+    fn the_edited_plan_reads_back() {
+        let plan =
+            plan("1. [structure] create src/cli\n2. [file src/cli/mod.rs] declare the module");
+
+        let steps = MutationPlan::parse_steps(&plan.render_for_editing()).unwrap();
+
+        assert_eq!(
+            steps
+                .iter()
+                .map(MutationPlanStep::render)
+                .collect::<Vec<_>>(),
+            [
+                "1. [structure] create src/cli",
+                "2. [file src/cli/mod.rs] declare the module"
+            ]
+        );
+
+        let draft = steps[1].source_file_mutation_draft(&plan).unwrap();
+
+        assert_eq!(draft.file_path, "/project/src/cli/mod.rs");
+        assert!(draft.create);
+        assert!(steps[0].source_file_mutation_draft(&plan).is_none());
+    }
+}
@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::blob::workspace::{project_tree_iter, structure_tree_iter};
+use crate::representation::tree::iterator::TreeIter;
+
+/// A read-only question about the project, nothing is ever written back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectQuestion {
+    pub path_root: String,
+    pub question: String,
+
+    pub context_lines: Option<Vec<String>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Citation {
+    pub file: String,
+    pub line: usize,
+    pub end_line: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectAnswer {
+    pub parent: Box<ProjectQuestion>,
+    pub files: Vec<String>,
+    pub answer: String,
+    pub citations: Vec<Citation>,
+}
+
+impl ProjectQuestion {
+    /// The context lines are selected once the files to answer from are known.
+    pub fn new(path_root: String, question: String) -> Self {
+        ProjectQuestion {
+            path_root,
+            question,
+            context_lines: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn tree_iter(&mut self) -> Box<TreeIter> {
+        project_tree_iter(&self.path_root)
+    }
+
+    /// The structure shown to the model, within the `[tree]` limits.
+    pub fn structure_tree_iter(&mut self) -> anyhow::Result<Box<TreeIter>> {
+        structure_tree_iter(&self.path_root, &self.question)
+    }
+}
+
+impl Citation {
+    /// Parse `file:12` or `file:12-20`, the file must be one of `files`.
+    fn parse(token: &str, files: &[String]) -> Option<Self> {
+        let token = token.trim_matches(|c: char| {
+            !(c.is_alphanumeric() || c == '/' || c == '.' || c == '_' || c == '-' || c == ':')
+        });
+
+        let (file, lines) = token.rsplit_once(':')?;
+        let file = file.trim_start_matches("./");
+
+        if !files.iter().any(|candidate| candidate == file) {
+            return None;
+        }
+
+        let lines = lines.trim_end_matches('.');
+
+        let (line, end_line) = match lines.split_once('-') {
+            Some((start, end)) => (start.parse().ok()?, Some(end.parse().ok()?)),
+            None => (lines.parse().ok()?, None),
+        };
+
+        Some(Citation {
+            file: file.to_string(),
+            line,
+            end_line,
+        })
+    }
+}
+
+impl ProjectAnswer {
+    pub fn new_from_parent(
+        parent: Box<ProjectQuestion>,
+        files: Vec<String>,
+        answer: String,
+    ) -> Self {
+        let mut citations: Vec<Citation> = Vec::new();
+
+        for token in answer.split_whitespace() {
+            if let Some(citation) = Citation::parse(token, &files) {
+                if !citations.contains(&citation) {
+                    citations.push(citation);
+                }
+            }
+        }
+
+        ProjectAnswer {
+            parent,
+            files,
+            answer,
+            citations,
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::blob::definitions::{
+    applicable_definitions, applicable_definitions_for_paths, BlobDefinition,
+};
+
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "in", "into", "is", "it", "of",
+    "on", "or", "that", "the", "this", "to", "with",
+];
+
+/// Lowercase words of the text, paths are split on their separators too.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(|word| word.to_lowercase())
+        .filter(|word| word.len() > 1 && !STOP_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// Rough token count used for budgeting, about 4 characters per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Okapi BM25 over a small set of documents, computed locally.
+pub struct Bm25Index {
+    documents: Vec<Vec<String>>,
+    document_frequency: HashMap<String, usize>,
+    average_length: f64,
+}
+
+impl Bm25Index {
+    pub fn new(documents: &[&str]) -> Self {
+        let documents = documents
+            .iter()
+            .map(|document| tokenize(document))
+            .collect::<Vec<_>>();
+
+        let mut document_frequency = HashMap::new();
+
+        for document in documents.iter() {
+            for term in document.iter().collect::<HashSet<_>>() {
+                *document_frequency.entry(term.clone()).or_insert(0) += 1;
+            }
+        }
+
+        let total_length = documents.iter().map(Vec::len).sum::<usize>();
+        let average_length = total_length as f64 / documents.len().max(1) as f64;
+
+        Bm25Index {
+            documents,
+            document_frequency,
+            average_length,
+        }
+    }
+
+    /// Score of the document at `index` and the query terms it contains.
+    pub fn score(&self, index: usize, query: &[String]) -> (f64, Vec<String>) {
+        let document = &self.documents[index];
+        let total = self.documents.len() as f64;
+        let length_ratio = document.len() as f64 / self.average_length.max(1.0);
+
+        let mut score = 0.0;
+        let mut matched = Vec::new();
+
+        for term in query.iter().collect::<HashSet<_>>() {
+            let frequency = document.iter().filter(|word| *word == term).count() as f64;
+
+            if frequency == 0.0 {
+                continue;
+            }
+
+            let containing = self.document_frequency[term] as f64;
+            let idf = (1.0 + (total - containing + 0.5) / (containing + 0.5)).ln();
+
+            score += idf * frequency * (BM25_K1 + 1.0)
+                / (frequency + BM25_K1 * (1.0 - BM25_B + BM25_B * length_ratio));
+
+            matched.push(term.clone());
+        }
+
+        matched.sort();
+
+        (score, matched)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RankedDefinition {
+    pub definition: BlobDefinition,
+    pub score: f64,
+    pub matched_terms: Vec<String>,
+    pub tokens: usize,
+    pub picked: bool,
+}
+
+/// Definitions chosen as context for one mutation target, or for the files of a
+/// review, a commit or an answer.
+#[derive(Debug, Clone)]
+pub struct ContextSelection {
+    pub target: Option<String>,
+    pub budget: usize,
+    /// Applicable definitions, from the least to the most specific.
+    pub ranked: Vec<RankedDefinition>,
+}
+
+impl ContextSelection {
+    /// Rank the definitions that apply to `target` against the query and the target
+    /// path, then pick the best ones while they fit in `budget` tokens. Ties go to the
+    /// most specific definition.
+    pub fn select(
+        definitions: &[BlobDefinition],
+        query: &str,
+        target: Option<&str>,
+        budget: usize,
+    ) -> Self {
+        let applicable = applicable_definitions(definitions, target);
+
+        ContextSelection::rank(applicable, query, target.map(str::to_string), budget)
+    }
+
+    /// Like [`ContextSelection::select`] for several files at once, the definitions
+    /// scoped to any of `paths` compete with the unscoped ones for the same budget.
+    pub fn select_for_paths(
+        definitions: &[BlobDefinition],
+        query: &str,
+        paths: &[String],
+        budget: usize,
+    ) -> Self {
+        let applicable = applicable_definitions_for_paths(definitions, paths);
+
+        let target = match paths.is_empty() {
+            true => None,
+            false => Some(paths.join(", ")),
+        };
+
+        ContextSelection::rank(applicable, query, target, budget)
+    }
+
+    fn rank(
+        applicable: Vec<&BlobDefinition>,
+        query: &str,
+        target: Option<String>,
+        budget: usize,
+    ) -> Self {
+        let index = Bm25Index::new(
+            &applicable
+                .iter()
+                .map(|definition| definition.text.as_str())
+                .collect::<Vec<_>>(),
+        );
+
+        let query = tokenize(&format!(
+            "{} {}",
+            query,
+            target.as_deref().unwrap_or_default()
+        ));
+
+        let mut ranked = applicable
+            .into_iter()
+            .enumerate()
+            .map(|(position, definition)| {
+                let (score, matched_terms) = index.score(position, &query);
+
+                RankedDefinition {
+                    definition: definition.clone(),
+                    score,
+                    matched_terms,
+                    tokens: estimate_tokens(&definition.text),
+                    picked: false,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut order = (0..ranked.len()).collect::<Vec<_>>();
+
+        order.sort_by(|a, b| ranked[*b].score.total_cmp(&ranked[*a].score).then(b.cmp(a)));
+
+        let mut used = 0;
+
+        for position in order {
+            if used + ranked[position].tokens <= budget {
+                used += ranked[position].tokens;
+                ranked[position].picked = true;
+            }
+        }
+
+        ContextSelection {
+            target,
+            budget,
+            ranked,
+        }
+    }
+
+    pub fn context_lines(&self) -> Vec<String> {
+        self.ranked
+            .iter()
+            .filter(|ranked| ranked.picked)
+            .map(|ranked| ranked.definition.text.clone())
+            .collect()
+    }
+
+    pub fn used_tokens(&self) -> usize {
+        self.ranked
+            .iter()
+            .filter(|ranked| ranked.picked)
+            .map(|ranked| ranked.tokens)
+            .sum()
+    }
+
+    /// One line per applicable definition, `+` when picked and `-` when left out.
+    pub fn render_explanation(&self) -> String {
+        let mut lines = vec![format!(
+            "Context for {} ({} of {} tokens):",
+            self.target.as_deref().unwrap_or("the project"),
+            self.used_tokens(),
+            self.budget
+        )];
+
+        if self.ranked.is_empty() {
+            lines.push("  no definitions apply".to_string());
+        }
+
+        for ranked in self.ranked.iter() {
+            let mut reasons = Vec::new();
+
+            if let Some(scope) = &ranked.definition.scope {
+                reasons.push(format!("scope {scope}"));
+            }
+
+            match ranked.matched_terms.is_empty() {
+                true => reasons.push("no matching terms".to_string()),
+                false => reasons.push(format!("matched {}", ranked.matched_terms.join(", "))),
+            }
+
+            if !ranked.picked {
+                reasons.push("over budget".to_string());
+            }
+
+            lines.push(format!(
+                "  {} {}  score {:.2}  {} tokens  {}  {}",
+                if ranked.picked { "+" } else { "-" },
+                ranked.definition.id,
+                ranked.score,
+                ranked.tokens,
+                reasons.join(", "),
+                ranked.definition.text
+            ));
+        }
+
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(text: &str, scope: Option<&str>) -> BlobDefinition {
+        BlobDefinition::new(text.to_string(), vec![], scope.map(str::to_string), None)
+    }
+
+    fn picked(selection: &ContextSelection) -> Vec<&str> {
+        selection
+            .ranked
+            .iter()
+            .filter(|ranked| ranked.picked)
+            .map(|ranked| ranked.definition.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn paths_and_sentences_are_tokenized_without_stop_words() {
+        assert_eq!(
+            tokenize("Add the retry to src/http_client.rs, a 2 x"),
+            ["add", "retry", "src", "http", "client", "rs"]
+        );
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+
+    #[test]
+    fn matching_documents_score_higher() {
+        let index = Bm25Index::new(&[
+            "retries use exponential backoff",
+            "logging goes through tracing",
+            "retries retries retries everywhere",
+        ]);
+
+        let query = tokenize("add retries to the client");
+
+        let (backoff, matched) = index.score(0, &query);
+        let (logging, _) = index.score(1, &query);
+        let (repeated, _) = index.score(2, &query);
+
+        assert_eq!(matched, ["retries"]);
+        assert_eq!(logging, 0.0);
+        assert!(backoff > 0.0);
+        // Term frequency saturates instead of growing linearly.
+        assert!(repeated < backoff * 3.0);
+    }
+
+    #[test]
+    fn the_best_definitions_are_picked_within_the_budget() {
+        let definitions = [
+            definition(
+                "database migrations live in db/migrations and run with sqlx",
+                None,
+            ),
+            definition("the database pool is shared through the app state", None),
+            definition("frontend uses tailwind", None),
+        ];
+
+        let selection = ContextSelection::select(&definitions, "add database migrations", None, 22);
+
+        // The second definition scores but doesn't fit next to the first one, the
+        // last one doesn't score but still fits.
+        assert_eq!(
+            picked(&selection),
+            [
+                "database migrations live in db/migrations and run with sqlx",
+                "frontend uses tailwind"
+            ]
+        );
+        assert_eq!(selection.used_tokens(), 21);
+        assert!(selection.render_explanation().contains("over budget"));
+    }
+
+    #[test]
+    fn ties_go_to_the_most_specific_definition() {
+        let definitions = [
+            definition("handlers return json", None),
+            definition("handlers return json", Some("src/api")),
+        ];
+
+        let selection =
+            ContextSelection::select(&definitions, "a handler", Some("src/api/users.rs"), 5);
+
+        assert_eq!(selection.ranked.len(), 2);
+        assert!(!selection.ranked[0].picked);
+        assert!(selection.ranked[1].picked);
+    }
+
+    #[test]
+    fn several_paths_share_the_budget() {
+        let definitions = [
+            definition("api handlers return json", Some("src/api")),
+            definition("cli output is plain text", Some("src/cli")),
+            definition("docs are written in markdown", Some("docs")),
+            definition("errors use anyhow", None),
+        ];
+
+        let paths = ["src/api/users.rs".to_string(), "src/cli/run.rs".to_string()];
+        let selection = ContextSelection::select_for_paths(&definitions, "fix", &paths, 1000);
+
+        assert_eq!(
+            picked(&selection),
+            [
+                "errors use anyhow",
+                "api handlers return json",
+                "cli output is plain text"
+            ]
+        );
+        assert_eq!(
+            selection.target.as_deref(),
+            Some("src/api/users.rs, src/cli/run.rs")
+        );
+
+        let unscoped = ContextSelection::select_for_paths(&definitions, "fix", &[], 1000);
+
+        assert_eq!(picked(&unscoped), ["errors use anyhow"]);
+        assert_eq!(unscoped.target, None);
+    }
+}
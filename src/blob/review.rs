@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewSeverity {
+    Info,
+    Warning,
+    Blocking,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewComment {
+    pub file: String,
+    pub line: Option<usize>,
+    pub severity: ReviewSeverity,
+    pub comment: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeReview {
+    pub target: String,
+    pub comments: Vec<ReviewComment>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ReviewSeverity {
+    fn parse(severity: &str) -> Option<Self> {
+        match severity.trim().to_lowercase().as_str() {
+            "info" | "note" | "nit" => Some(ReviewSeverity::Info),
+            "warning" | "warn" => Some(ReviewSeverity::Warning),
+            "blocking" | "error" | "critical" => Some(ReviewSeverity::Blocking),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewSeverity::Info => "info",
+            ReviewSeverity::Warning => "warning",
+            ReviewSeverity::Blocking => "blocking",
+        }
+    }
+}
+
+impl ReviewComment {
+    /// Parse the comments of a file, one per line as `LINE | SEVERITY | comment`.
+    /// `-` can be used as line for comments about the whole file.
+    pub fn parse_all(file: &str, text: &str) -> Vec<Self> {
+        text.lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(3, '|');
+
+                let line_number = parts.next()?.trim();
+                let severity = ReviewSeverity::parse(parts.next()?)?;
+                let comment = parts.next()?.trim();
+
+                if comment.is_empty() {
+                    return None;
+                }
+
+                Some(ReviewComment {
+                    file: file.to_string(),
+                    line: line_number.trim_start_matches('L').parse().ok(),
+                    severity,
+                    comment: comment.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    fn location(&self) -> String {
+        match self.line {
+            Some(line) => format!("{}:{}", self.file, line),
+            None => self.file.clone(),
+        }
+    }
+}
+
+impl CodeReview {
+    pub fn new(target: String, mut comments: Vec<ReviewComment>) -> Self {
+        comments.sort_by(|a, b| {
+            (&a.file, a.line)
+                .cmp(&(&b.file, b.line))
+                .then(b.severity.cmp(&a.severity))
+        });
+
+        CodeReview {
+            target,
+            comments,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn has_findings_at_least(&self, severity: ReviewSeverity) -> bool {
+        self.comments.iter().any(|c| c.severity >= severity)
+    }
+
+    pub fn render_terminal(&self) -> String {
+        if self.comments.is_empty() {
+            return format!("No findings for {}.\n", self.target);
+        }
+
+        let mut output = String::new();
+
+        for comment in &self.comments {
+            output.push_str(&format!(
+                "[{}] {}\n    {}\n",
+                comment.severity.as_str(),
+                comment.location(),
+                comment.comment
+            ));
+        }
+
+        output
+    }
+
+    pub fn render_markdown(&self) -> String {
+        let mut output = format!("# Review of {}\n\n", self.target);
+
+        if self.comments.is_empty() {
+            output.push_str("No findings.\n");
+            return output;
+        }
+
+        let mut current_file: Option<&str> = None;
+
+        for comment in &self.comments {
+            if current_file != Some(comment.file.as_str()) {
+                output.push_str(&format!("## `{}`\n\n", comment.file));
+                current_file = Some(comment.file.as_str());
+            }
+
+            let line = comment
+                .line
+                .map(|line| format!("L{line} "))
+                .unwrap_or_default();
+
+            output.push_str(&format!(
+                "- **{}** {}{}\n",
+                comment.severity.as_str(),
+                line,
+                comment.comment
+            ));
+        }
+
+        output
+    }
+}
@@ -0,0 +1,409 @@
+use std::env;
+use std::fs::{copy, create_dir_all, read_dir, remove_dir, remove_dir_all, remove_file, write};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, Once};
+
+use anyhow::{anyhow, Result};
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::blob::config::{CheckConfig, CheckWorkspace};
+use crate::blob::mutation::{ProjectMutation, SourceFileMutation};
+use crate::representation::tree::filters::{FileFilter, IgnoreFileFilter};
+
+/// Only the head of the check output is sent back to the model, compilers and
+/// test runners report the most useful error first.
+const MAX_OUTPUT_EXCERPT: usize = 4_000;
+
+#[derive(Debug, Clone)]
+pub struct CheckCommand {
+    pub command: String,
+    pub workspace: CheckWorkspace,
+    project_root: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckOutcome {
+    pub command: String,
+    pub success: bool,
+    pub output: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerificationReport {
+    pub attempts: Vec<CheckOutcome>,
+}
+
+struct ScratchCopy {
+    path: PathBuf,
+}
+
+impl ScratchCopy {
+    /// Copy the project without what its ignore files exclude, build outputs and
+    /// installed dependencies would make every attempt slow.
+    fn new(project_root: &str) -> Result<Self> {
+        let path = env::temp_dir().join(format!(
+            "blob-check-{}-{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos()
+        ));
+
+        let scratch = ScratchCopy { path };
+        let filter = IgnoreFileFilter::new(PathBuf::from(project_root));
+
+        copy_filtered(Path::new(project_root), &scratch.path, &filter).map_err(|err| {
+            anyhow!(
+                "Failed to copy the project into {}: {err}",
+                scratch.path.display()
+            )
+        })?;
+
+        Ok(scratch)
+    }
+}
+
+fn copy_filtered(from: &Path, to: &Path, filter: &IgnoreFileFilter) -> Result<()> {
+    create_dir_all(to)?;
+
+    for entry in read_dir(from)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !filter.filter(&path).map_err(|err| anyhow!("{err}"))? {
+            continue;
+        }
+
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        if file_type.is_symlink() {
+            copy_link(&path, &target)?;
+        } else if file_type.is_dir() {
+            copy_filtered(&path, &target, filter)?;
+        } else if file_type.is_file() {
+            copy(&path, &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_link(link: &Path, target: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(link)?, target)?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_link(link: &Path, target: &Path) -> Result<()> {
+    if link.is_file() {
+        copy(link, target)?;
+    }
+
+    Ok(())
+}
+
+impl Drop for ScratchCopy {
+    fn drop(&mut self) {
+        remove_dir_all(&self.path).ok();
+    }
+}
+
+/// Whether a path relative to the scratch copy climbs above it with `..`.
+fn climbs_out(path: &str) -> bool {
+    let mut depth = 0usize;
+
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return true,
+            },
+            Component::Normal(_) => depth += 1,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+/// Swaps a file of the working tree for a check. The original is copied under
+/// `.blob/` first and put back when the guard is dropped, or by the Ctrl-C listener
+/// when the check is interrupted since nothing is dropped then.
+struct WorkingTreeSwap {
+    file: PathBuf,
+}
+
+/// Swapped files that still have to be put back, with their backup.
+static PENDING_RESTORES: Mutex<Vec<(PathBuf, Option<PathBuf>)>> = Mutex::new(Vec::new());
+
+static INTERRUPT_LISTENER: Once = Once::new();
+
+/// Put the swapped files back on Ctrl-C, then exit like the interrupt would have.
+fn listen_for_interrupts() {
+    INTERRUPT_LISTENER.call_once(|| {
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    let mut pending = PENDING_RESTORES
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner());
+
+                    for (file, backup) in pending.drain(..) {
+                        restore(&file, backup.as_deref());
+                    }
+
+                    std::process::exit(130);
+                }
+            });
+        }
+    });
+}
+
+fn restore(file: &Path, backup: Option<&Path>) {
+    match backup {
+        Some(backup) => match copy(backup, file) {
+            Ok(_) => {
+                remove_file(backup).ok();
+
+                // Only goes away once no other check keeps a backup in it.
+                if let Some(backups) = backup.parent() {
+                    remove_dir(backups).ok();
+                }
+            }
+            Err(err) => eprintln!(
+                "Couldn't restore {} ({err}), the original is kept in {}",
+                file.display(),
+                backup.display()
+            ),
+        },
+        None => {
+            remove_file(file).ok();
+        }
+    }
+}
+
+impl WorkingTreeSwap {
+    fn new(project_root: &str, file_path: &str, content: &str) -> Result<Self> {
+        let file = PathBuf::from(file_path);
+
+        let backup = match file.exists() {
+            true => {
+                let backups = Path::new(project_root).join(".blob").join(".check-backups");
+                create_dir_all(&backups)?;
+
+                let name = file
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let backup = backups.join(format!("{}-{name}", std::process::id()));
+
+                copy(&file, &backup)?;
+                Some(backup)
+            }
+            false => None,
+        };
+
+        listen_for_interrupts();
+
+        PENDING_RESTORES
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push((file.clone(), backup));
+
+        let swap = WorkingTreeSwap { file };
+
+        write(&swap.file, content)?;
+
+        Ok(swap)
+    }
+}
+
+impl Drop for WorkingTreeSwap {
+    fn drop(&mut self) {
+        let mut pending = PENDING_RESTORES
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        // Already put back when the listener got there first.
+        if let Some(index) = pending.iter().position(|(file, _)| *file == self.file) {
+            let (file, backup) = pending.remove(index);
+            restore(&file, backup.as_deref());
+        }
+    }
+}
+
+impl CheckOutcome {
+    pub fn excerpt(&self) -> String {
+        match self.output.char_indices().nth(MAX_OUTPUT_EXCERPT) {
+            Some((end, _)) => format!("{}\n[... output truncated]", &self.output[..end]),
+            None => self.output.clone(),
+        }
+    }
+}
+
+impl VerificationReport {
+    pub fn last_failure(&self) -> Option<&CheckOutcome> {
+        self.attempts.last().filter(|a| !a.success)
+    }
+}
+
+impl CheckCommand {
+    pub fn new(project_root: String, command: String, workspace: CheckWorkspace) -> Self {
+        CheckCommand {
+            command,
+            workspace,
+            project_root,
+        }
+    }
+
+    /// Build the check from the config, `command` overrides the configured one.
+    pub fn from_config(
+        project_root: String,
+        config: &CheckConfig,
+        command: Option<String>,
+    ) -> Option<Self> {
+        command
+            .or_else(|| config.command.clone())
+            .map(|command| CheckCommand::new(project_root, command, config.workspace))
+    }
+
+    /// Run the command as is in the project root.
+    pub fn run(&self) -> Result<CheckOutcome> {
+        self.run_in(Path::new(&self.project_root))
+    }
+
+    fn run_in(&self, dir: &Path) -> Result<CheckOutcome> {
+        let res = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} 2>&1", self.command))
+            .current_dir(dir)
+            .output()?;
+
+        Ok(CheckOutcome {
+            command: self.command.clone(),
+            success: res.status.success(),
+            output: String::from_utf8_lossy(&res.stdout).to_string(),
+        })
+    }
+
+    /// Path of `file_path` relative to the project root.
+    fn relative_path(&self, file_path: &str) -> Result<PathBuf> {
+        let root = std::path::absolute(&self.project_root)?;
+        let file = std::path::absolute(file_path)?;
+
+        file.strip_prefix(&root)
+            .map(Path::to_path_buf)
+            .map_err(|_| anyhow!("'{}' is outside of '{}'", file_path, self.project_root))
+    }
+
+    /// Point the project paths of a mutation script at the scratch copy, any other
+    /// absolute path, or relative path climbing out of the copy, would let the script
+    /// change files outside of it.
+    fn scratch_commands(&self, commands: &str, scratch: &Path) -> Result<String> {
+        let mut commands = commands.to_string();
+
+        let roots = [
+            Path::new(&self.project_root).canonicalize().ok(),
+            std::path::absolute(&self.project_root).ok(),
+        ];
+
+        for root in roots.into_iter().flatten() {
+            commands =
+                commands.replace(&root.display().to_string(), &scratch.display().to_string());
+        }
+
+        let outside = commands
+            .split(|c: char| {
+                c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>' | '&' | '=' | ';' | '|')
+            })
+            .find(
+                |word| match word.strip_prefix(&scratch.display().to_string()) {
+                    Some(inside) => climbs_out(inside),
+                    None => {
+                        ((word.starts_with('/') || word.starts_with('~'))
+                            && !word.starts_with("/dev/null"))
+                            || climbs_out(word)
+                    }
+                },
+            );
+
+        match outside {
+            Some(path) => Err(anyhow!(
+                "The mutation script uses the path '{path}' outside of the project, \
+                 only paths inside the project root can be checked"
+            )),
+            None => Ok(commands),
+        }
+    }
+
+    pub fn check_source_file_mutation(
+        &self,
+        mutation: &SourceFileMutation,
+    ) -> Result<CheckOutcome> {
+        let file_path = mutation.parent.file_path.clone();
+
+        match self.workspace {
+            CheckWorkspace::Scratch => {
+                let scratch = ScratchCopy::new(&self.project_root)?;
+                let target = scratch.path.join(self.relative_path(&file_path)?);
+
+                if let Some(parent) = target.parent() {
+                    create_dir_all(parent)?;
+                }
+
+                write(&target, &mutation.proposed_content)?;
+
+                self.run_in(&scratch.path)
+            }
+            CheckWorkspace::WorkingTree => {
+                let _swap = WorkingTreeSwap::new(
+                    &self.project_root,
+                    &file_path,
+                    &mutation.proposed_content,
+                )?;
+
+                self.run_in(Path::new(&self.project_root))
+            }
+        }
+    }
+
+    pub fn check_project_mutation(&self, mutation: &ProjectMutation) -> Result<CheckOutcome> {
+        let scratch = ScratchCopy::new(&self.project_root)?;
+
+        let commands = match self.scratch_commands(&mutation.predicted_commands, &scratch.path) {
+            Ok(commands) => commands,
+            Err(err) => {
+                return Ok(CheckOutcome {
+                    command: "mutation script".to_string(),
+                    success: false,
+                    output: err.to_string(),
+                })
+            }
+        };
+
+        let script = format!("cd {}\n{}", scratch.path.display(), commands);
+
+        let res = Command::new("bash")
+            .arg("-c")
+            .arg(script)
+            .current_dir(&scratch.path)
+            .output()?;
+
+        if !res.status.success() {
+            return Ok(CheckOutcome {
+                command: "mutation script".to_string(),
+                success: false,
+                output: format!(
+                    "{}{}",
+                    String::from_utf8_lossy(&res.stdout),
+                    String::from_utf8_lossy(&res.stderr)
+                ),
+            });
+        }
+
+        self.run_in(&scratch.path)
+    }
+}
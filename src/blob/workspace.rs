@@ -0,0 +1,316 @@
+use std::fs::{read_to_string, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::blob::config::BlobConfig;
+use crate::representation::tree::filters::{
+    BinaryFilter, FilterAggregate, GlobFilter, HiddenFilter, IgnoreFileFilter, SizeFilter,
+};
+use crate::representation::tree::iterator::TreeIter;
+
+/// Dependencies listed in a seeded definition, the rest are left out.
+const MAX_SEEDED_DEPENDENCIES: usize = 15;
+
+/// Walk the project files in the `[tree]` order, skipping the `.blob` workspace,
+/// ignored files and whatever `[filters]` leaves out. Submodules are listed without
+/// their content and symbolic links as `[tree] symlinks` says.
+pub fn project_tree_iter(path_root: &str) -> Box<TreeIter> {
+    tree_iter_from(path_root, Path::new(path_root)).unwrap()
+}
+
+/// Walk the folder `start` of the project like [`project_tree_iter`], the ignore files
+/// and filters of the whole project still apply.
+pub fn tree_iter_from(path_root: &str, start: &Path) -> Result<Box<TreeIter>> {
+    // The config is validated when blob starts, see `BlobConfig::load`.
+    let blob_config = BlobConfig::load(path_root).unwrap_or_default();
+    let config = blob_config.filters;
+
+    let root = PathBuf::from(path_root);
+
+    let mut filters = FilterAggregate::default();
+
+    filters.push(|path: &Path| Ok(path.file_name().is_none_or(|name| name != ".blob")));
+    filters.push(IgnoreFileFilter::new(root.clone()));
+    filters.push(IgnoreFileFilter::blobignore(root.clone()));
+    filters.push(GlobFilter::new(root.clone(), &config.include, &config.exclude).unwrap());
+    filters.push(SizeFilter {
+        max_bytes: config.max_file_size,
+    });
+
+    if !config.hidden {
+        filters.push(HiddenFilter);
+    }
+
+    if !config.binary {
+        filters.push(BinaryFilter);
+    }
+
+    let tree = TreeIter::new(start.to_path_buf(), filters, blob_config.tree.order())
+        .and_then(|tree| tree.with_symlinks(blob_config.tree.symlinks))
+        .map_err(|err| anyhow!("{err}"))?;
+
+    Ok(Box::new(tree))
+}
+
+/// Walk the project like [`project_tree_iter`] within the `[tree]` depth and folding
+/// limits, always listing the paths mentioned by `instruction`.
+pub fn structure_tree_iter(path_root: &str, instruction: &str) -> Result<Box<TreeIter>> {
+    let limits = BlobConfig::load(path_root)?
+        .tree
+        .limits()
+        .focus_on(instruction);
+
+    let tree = project_tree_iter(path_root)
+        .with_limits(limits)
+        .map_err(|err| anyhow!("{err}"))?;
+
+    Ok(Box::new(tree))
+}
+
+/// Definitions guessed from the README and the manifest files found at the root.
+pub fn seed_definitions(project_root: &str) -> Vec<String> {
+    let root = Path::new(project_root);
+
+    let mut definitions = Vec::new();
+
+    if let Some(definition) = read_to_string(root.join("README.md"))
+        .ok()
+        .and_then(|readme| readme_definition(&readme))
+    {
+        definitions.push(definition);
+    }
+
+    if let Ok(manifest) = read_to_string(root.join("Cargo.toml")) {
+        definitions.extend(cargo_definitions(&manifest));
+    }
+
+    if let Ok(manifest) = read_to_string(root.join("package.json")) {
+        definitions.extend(package_json_definitions(&manifest));
+    }
+
+    if let Ok(manifest) = read_to_string(root.join("pyproject.toml")) {
+        definitions.extend(pyproject_definitions(&manifest));
+    }
+
+    if let Ok(manifest) = read_to_string(root.join("go.mod")) {
+        definitions.extend(go_mod_definitions(&manifest));
+    }
+
+    definitions
+}
+
+/// Title and first paragraph of the README, skipping badges, images and html.
+fn readme_definition(readme: &str) -> Option<String> {
+    let title = readme
+        .lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(str::trim);
+
+    let paragraph = readme
+        .split("\n\n")
+        .map(|block| block.lines().map(str::trim).collect::<Vec<_>>().join(" "))
+        .find(|block| {
+            !block.is_empty()
+                && !block.starts_with('#')
+                && !block.starts_with('!')
+                && !block.starts_with('>')
+                && !block.starts_with('<')
+                && !block.starts_with('[')
+                && !block.starts_with("```")
+        })?;
+
+    // Long paragraphs are cut at the last full sentence that fits.
+    let paragraph = match paragraph.char_indices().nth(300) {
+        Some((index, _)) => match paragraph[..index].rfind(". ") {
+            Some(end) => paragraph[..=end].to_string(),
+            None => format!("{}...", &paragraph[..index]),
+        },
+        None => paragraph,
+    };
+
+    Some(match title {
+        Some(title) => format!("{title}: {paragraph}"),
+        None => paragraph,
+    })
+}
+
+fn dependencies_definition(ecosystem: &str, mut dependencies: Vec<String>) -> Option<String> {
+    if dependencies.is_empty() {
+        return None;
+    }
+
+    dependencies.sort();
+    dependencies.truncate(MAX_SEEDED_DEPENDENCIES);
+
+    Some(format!(
+        "the project uses the {} dependencies {}",
+        ecosystem,
+        dependencies.join(", ")
+    ))
+}
+
+fn described(kind: &str, name: &str, description: Option<&str>) -> String {
+    match description {
+        Some(description) => format!("this project is a {kind} named {name}: {description}"),
+        None => format!("this project is a {kind} named {name}"),
+    }
+}
+
+fn cargo_definitions(manifest: &str) -> Vec<String> {
+    let manifest = match manifest.parse::<toml::Table>() {
+        Ok(manifest) => manifest,
+        Err(_) => return vec![],
+    };
+
+    let mut definitions = Vec::new();
+
+    if let Some(package) = manifest.get("package") {
+        if let Some(name) = package.get("name").and_then(|name| name.as_str()) {
+            let description = package
+                .get("description")
+                .and_then(|description| description.as_str());
+
+            definitions.push(described("Rust crate", name, description));
+        }
+    }
+
+    if let Some(workspace) = manifest.get("workspace").and_then(|w| w.get("members")) {
+        if let Some(members) = workspace.as_array() {
+            let members = members
+                .iter()
+                .filter_map(|member| member.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            definitions.push(format!("the Rust workspace has the members {members}"));
+        }
+    }
+
+    let dependencies = manifest
+        .get("dependencies")
+        .and_then(|dependencies| dependencies.as_table())
+        .map(|dependencies| dependencies.keys().cloned().collect())
+        .unwrap_or_default();
+
+    definitions.extend(dependencies_definition("Rust", dependencies));
+
+    definitions
+}
+
+fn package_json_definitions(manifest: &str) -> Vec<String> {
+    let manifest = match serde_json::from_str::<serde_json::Value>(manifest) {
+        Ok(manifest) => manifest,
+        Err(_) => return vec![],
+    };
+
+    let mut definitions = Vec::new();
+
+    if let Some(name) = manifest["name"].as_str() {
+        definitions.push(described(
+            "JavaScript package",
+            name,
+            manifest["description"].as_str(),
+        ));
+    }
+
+    let dependencies = manifest["dependencies"]
+        .as_object()
+        .map(|dependencies| dependencies.keys().cloned().collect())
+        .unwrap_or_default();
+
+    definitions.extend(dependencies_definition("npm", dependencies));
+
+    if let Some(scripts) = manifest["scripts"].as_object() {
+        if !scripts.is_empty() {
+            definitions.push(format!(
+                "the package.json scripts are {}",
+                scripts.keys().cloned().collect::<Vec<_>>().join(", ")
+            ));
+        }
+    }
+
+    definitions
+}
+
+fn pyproject_definitions(manifest: &str) -> Vec<String> {
+    let manifest = match manifest.parse::<toml::Table>() {
+        Ok(manifest) => manifest,
+        Err(_) => return vec![],
+    };
+
+    let project = match manifest.get("project") {
+        Some(project) => project,
+        None => return vec![],
+    };
+
+    let mut definitions = Vec::new();
+
+    if let Some(name) = project.get("name").and_then(|name| name.as_str()) {
+        let description = project
+            .get("description")
+            .and_then(|description| description.as_str());
+
+        definitions.push(described("Python package", name, description));
+    }
+
+    // Requirements look like `requests>=2.0`, only the name is kept.
+    let dependencies = project
+        .get("dependencies")
+        .and_then(|dependencies| dependencies.as_array())
+        .map(|dependencies| {
+            dependencies
+                .iter()
+                .filter_map(|dependency| dependency.as_str())
+                .filter_map(|dependency| {
+                    dependency
+                        .split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+                        .next()
+                        .map(str::to_string)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    definitions.extend(dependencies_definition("Python", dependencies));
+
+    definitions
+}
+
+fn go_mod_definitions(manifest: &str) -> Vec<String> {
+    manifest
+        .lines()
+        .find_map(|line| line.strip_prefix("module "))
+        .map(|module| {
+            vec![format!(
+                "this project is a Go module named {}",
+                module.trim()
+            )]
+        })
+        .unwrap_or_default()
+}
+
+/// Append `entry` to the `.gitignore` of the project, returns `false` if it was there.
+pub fn ensure_gitignore_entry(project_root: &str, entry: &str) -> Result<bool> {
+    let path = Path::new(project_root).join(".gitignore");
+
+    let existing = read_to_string(&path).unwrap_or_default();
+
+    if existing
+        .lines()
+        .any(|line| line.trim().trim_start_matches('/').trim_end_matches('/') == entry)
+    {
+        return Ok(false);
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file)?;
+    }
+
+    writeln!(file, "{entry}")?;
+
+    Ok(true)
+}
@@ -0,0 +1,10 @@
+use similar::TextDiff;
+
+/// Unified diff between the current and the proposed content of a file.
+pub fn unified_file_diff(file_path: &str, current: &str, proposed: &str) -> String {
+    TextDiff::from_lines(current, proposed)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{file_path}"), &format!("b/{file_path}"))
+        .to_string()
+}
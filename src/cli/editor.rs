@@ -0,0 +1,39 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+use anyhow::{anyhow, Result};
+
+/// Open `content` in `$VISUAL`/`$EDITOR` (falling back to `vi`) and return the edited text.
+pub fn edit_in_editor(content: &str, extension: &str) -> Result<String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    let file_path = env::temp_dir().join(format!(
+        "blob-{}-{}.{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_nanos(),
+        extension
+    ));
+
+    fs::write(&file_path, content)?;
+
+    // The editor may carry its own arguments, e.g. `code --wait`.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&file_path)
+        .status()?;
+
+    let edited = fs::read_to_string(&file_path);
+
+    fs::remove_file(&file_path).ok();
+
+    if !status.success() {
+        return Err(anyhow!("Editor '{}' exited with {}", editor, status));
+    }
+
+    Ok(edited?)
+}
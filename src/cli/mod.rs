@@ -1 +1,5 @@
+pub mod diff;
+pub mod editor;
+pub mod runner;
 pub mod tool;
+pub mod tree_diff;
@@ -0,0 +1,695 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, Result};
+use git2::{Oid, Status};
+
+use crate::blob::config::{CommitMode, ContextConfig};
+use crate::blob::context::{BlobContextProcessor, BlobMutationCommit};
+use crate::blob::mutation::{
+    mutation_id, ProjectMutationDraft, ProjectMutationProposed, SourceFileMutation,
+    SourceFileMutationDraft, SourceFileMutationGroup,
+};
+use crate::blob::plan::{
+    MutationPlan, MutationPlanStep, MutationPlanStepKind, MutationPlanStepStatus,
+};
+use crate::blob::verification::{CheckCommand, VerificationReport};
+use crate::cli::diff::unified_file_diff;
+use crate::cli::editor::edit_in_editor;
+use crate::cli::tree_diff::{render_structure_diff, use_colour};
+use crate::llm::engine::LLMEngine;
+use crate::structure::software::{is_staged, Project};
+
+pub fn ask_for_confirmation() -> bool {
+    ask_yes_no("Do you want to apply this mutation?")
+}
+
+pub fn ask_yes_no(question: &str) -> bool {
+    println!("{question} (y/N):");
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).unwrap();
+
+    matches!(input.trim(), "y" | "yes")
+}
+
+enum Review {
+    Approve,
+    Edit,
+    Reject,
+}
+
+fn ask_for_review(question: &str) -> Review {
+    println!("{question} (y)es / (e)dit / (N)o:");
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).unwrap();
+
+    match input.trim() {
+        "y" | "yes" => Review::Approve,
+        "e" | "edit" => Review::Edit,
+        _ => Review::Reject,
+    }
+}
+
+fn print_structure_proposal(proposal: &ProjectMutationProposed) {
+    match render_structure_diff(
+        proposal.format,
+        proposal.current_structure(),
+        proposal.proposed_structure(),
+        use_colour(),
+    ) {
+        Ok(Some(diff)) => println!("Proposed structure:\n{diff}\n"),
+        Ok(None) => println!("The structure doesn't change.\n"),
+        Err(err) => println!(
+            "Proposed structure ({err}):\n{}\n",
+            proposal.proposed_structure().trim_end()
+        ),
+    }
+}
+
+/// Let the user check the proposed structure, and fix it in `$EDITOR`, before any
+/// command is generated. Returns `None` when it's rejected.
+fn review_structure_proposal(
+    mut proposal: Box<ProjectMutationProposed>,
+) -> Option<Box<ProjectMutationProposed>> {
+    loop {
+        print_structure_proposal(&proposal);
+
+        match ask_for_review("Do you want to continue with this structure?") {
+            Review::Approve => return Some(proposal),
+            Review::Edit => {
+                let edited = match edit_in_editor(
+                    proposal.proposed_structure(),
+                    proposal.format.extension(),
+                ) {
+                    Ok(edited) => edited,
+                    Err(err) => {
+                        println!("{err:#}, keeping the previous structure.");
+                        continue;
+                    }
+                };
+
+                match proposal
+                    .format
+                    .validate_edit(proposal.current_structure(), &edited)
+                {
+                    Ok(_) => proposal.set_proposed_structure(edited),
+                    Err(err) => println!("{err}, keeping the previous structure."),
+                }
+            }
+            Review::Reject => return None,
+        }
+    }
+}
+
+pub fn apply_source_file_mutation(
+    mutation_folder_path: String,
+    source_file_mutation: SourceFileMutation,
+) {
+    if let Some(parent) = Path::new(&source_file_mutation.parent.file_path).parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+
+    let res = Command::new("cp")
+        .arg("-r")
+        .arg(mutation_folder_path)
+        .arg(source_file_mutation.parent.file_path)
+        .output()
+        .unwrap();
+
+    let output = String::from_utf8_lossy(&res.stdout);
+
+    println!("{}", output);
+}
+
+pub fn apply_mutation_script(mutation_script_path: String) {
+    let res = Command::new("bash")
+        .arg(mutation_script_path)
+        .output()
+        .unwrap();
+    let output = String::from_utf8_lossy(&res.stdout);
+
+    println!("{}", output);
+}
+
+/// Where a mutation is stored inside `.blob/.mutations`.
+#[derive(Clone, Copy)]
+pub enum MutationTarget<'a> {
+    Standalone,
+    PlanStep(&'a MutationPlan, &'a MutationPlanStep),
+    /// A project mutation resumed with `blob resume`, saved back under its id.
+    Resumed(&'a str),
+}
+
+impl MutationTarget<'_> {
+    fn mutation_id(&self, created_at: &chrono::DateTime<chrono::Utc>) -> String {
+        match self {
+            MutationTarget::Standalone => mutation_id(created_at),
+            MutationTarget::PlanStep(plan, step) => step.mutation_id(plan),
+            MutationTarget::Resumed(id) => id.to_string(),
+        }
+    }
+}
+
+/// State of the repository right before a mutation is applied, used to tell
+/// which paths the mutation touched. Dirty paths keep the id of their content,
+/// a file edited again keeps its status.
+struct CommitSnapshot {
+    project: Project,
+    dirty_before: BTreeMap<String, (Status, Option<Oid>)>,
+}
+
+/// Drives a mutation from the draft to the user's decision: generation,
+/// optional verification, persistence, confirmation and application.
+pub struct MutationRunner<'a> {
+    pub engine: &'a mut LLMEngine,
+    pub context_processor: &'a BlobContextProcessor,
+    pub check: Option<CheckCommand>,
+    pub max_repairs: usize,
+    pub project_root: String,
+    pub commit_mode: CommitMode,
+    /// How the project definitions of each mutation are selected.
+    pub context: ContextConfig,
+    /// Print which definitions were picked for each mutation.
+    pub explain_context: bool,
+}
+
+fn report_verification(report: &VerificationReport) -> bool {
+    match report.last_failure() {
+        Some(failure) => {
+            println!(
+                "`{}` still fails after {} attempt(s), last output:\n{}",
+                failure.command,
+                report.attempts.len(),
+                failure.output.trim_end()
+            );
+
+            false
+        }
+        None => {
+            if !report.attempts.is_empty() {
+                println!("Check passed.");
+            }
+
+            true
+        }
+    }
+}
+
+impl<'a> MutationRunner<'a> {
+    /// Refuses to commit when the user's own changes would end up in the commit:
+    /// anything staged, or uncommitted edits to the files the mutation changes.
+    fn commit_snapshot(&self, touched_files: &[String]) -> Result<Option<CommitSnapshot>> {
+        if self.commit_mode == CommitMode::Off {
+            return Ok(None);
+        }
+
+        Project::new(PathBuf::from(&self.project_root)).and_then(|project| {
+            let dirty = project.dirty_paths()?;
+
+            if dirty.values().any(|status| is_staged(*status)) {
+                return Err(anyhow!(
+                    "the index has staged changes, commit or unstage them first"
+                ));
+            }
+
+            if let Some(path) = touched_files
+                .iter()
+                .filter_map(|file| project.relative_path(file))
+                .find(|path| dirty.contains_key(path))
+            {
+                return Err(anyhow!(
+                    "{path} has uncommitted changes, commit or stash them first"
+                ));
+            }
+
+            let dirty_before = dirty
+                .into_iter()
+                .map(|(path, status)| {
+                    let content = project.working_file_id(&path);
+                    (path, (status, content))
+                })
+                .collect();
+
+            Ok(Some(CommitSnapshot {
+                project,
+                dirty_before,
+            }))
+        })
+    }
+
+    async fn create_commit(
+        &mut self,
+        snapshot: &CommitSnapshot,
+        id: &str,
+        instruction: &str,
+        touched_files: &[String],
+    ) -> Result<BlobMutationCommit> {
+        let project = &snapshot.project;
+
+        let mut paths: BTreeSet<String> = touched_files
+            .iter()
+            .filter_map(|file| project.relative_path(file))
+            .collect();
+
+        let dirty_after = project.dirty_paths()?;
+
+        for (path, status) in &dirty_after {
+            if !snapshot.dirty_before.contains_key(path) {
+                paths.insert(path.clone());
+            }
+
+            if let Some((status_before, content_before)) = snapshot.dirty_before.get(path) {
+                if status_before != status || *content_before != project.working_file_id(path) {
+                    paths.insert(path.clone());
+                }
+            }
+        }
+
+        // Dirty paths the mutation brought back to their committed state.
+        for path in snapshot.dirty_before.keys() {
+            if !dirty_after.contains_key(path) {
+                paths.insert(path.clone());
+            }
+        }
+
+        paths.retain(|path| !path.starts_with(".blob/"));
+
+        // The user's edits can't be told apart from the mutation's.
+        if let Some(path) = paths
+            .iter()
+            .find(|path| snapshot.dirty_before.contains_key(*path))
+        {
+            return Err(anyhow!(
+                "{path} already had uncommitted changes before the mutation"
+            ));
+        }
+
+        if paths.is_empty() {
+            return Err(anyhow!("the mutation didn't change any file"));
+        }
+
+        let branch = match self.commit_mode {
+            CommitMode::Branch => {
+                let name = format!("blob/{id}");
+                project.create_and_switch_branch(&name)?;
+                Some(name)
+            }
+            _ => None,
+        };
+
+        let paths: Vec<String> = paths.into_iter().collect();
+
+        let tree = project.tree_with_paths(&paths)?;
+
+        let diff = project.tree_patch(tree, 8_000)?;
+
+        let message = self
+            .engine
+            .generate_commit_message(Some(instruction), &diff, vec![])
+            .await?;
+
+        let message = format!("{}\n\nBlob-Mutation-Id: {}\n", message.trim(), id);
+
+        let commit_id = project.commit_tree(tree, &message)?;
+
+        // The index was clean, it only has to follow the committed paths.
+        project.stage_paths(&paths)?;
+
+        Ok(BlobMutationCommit {
+            commit_id,
+            branch,
+            reverted_by: None,
+            previous: None,
+        })
+    }
+
+    /// Commit what the mutation changed and record the commit in its metadata.
+    async fn commit_mutation(
+        &mut self,
+        snapshot: Option<CommitSnapshot>,
+        id: &str,
+        instruction: &str,
+        touched_files: &[String],
+    ) {
+        let Some(snapshot) = snapshot else {
+            return;
+        };
+
+        match self
+            .create_commit(&snapshot, id, instruction, touched_files)
+            .await
+        {
+            Ok(commit) => {
+                match &commit.branch {
+                    Some(branch) => println!("Committed {} on {}", commit.commit_id, branch),
+                    None => println!("Committed {}", commit.commit_id),
+                }
+
+                self.context_processor.record_mutation_commit(id, commit);
+            }
+            Err(err) => println!("Failed to commit the mutation: {err}"),
+        }
+    }
+
+    /// Definitions for the file of the draft, ranked against its instruction.
+    fn select_context(&self, mutation_draft: &SourceFileMutationDraft) -> Vec<String> {
+        let selection = self.context_processor.select_project_context(
+            &mutation_draft.prompt,
+            Some(&mutation_draft.file_path),
+            &self.context,
+        );
+
+        if self.explain_context {
+            println!("{}", selection.render_explanation());
+        }
+
+        selection.context_lines()
+    }
+
+    /// Returns whether the mutation was applied.
+    pub async fn run_source_file_mutation(
+        &mut self,
+        mut mutation_draft: SourceFileMutationDraft,
+        target: MutationTarget<'_>,
+    ) -> bool {
+        mutation_draft.context_lines = self.select_context(&mutation_draft);
+
+        let mut source_file_mutation = match self
+            .engine
+            .transform_specific_file(Box::new(mutation_draft))
+            .await
+        {
+            Ok(source_file_mutation) => source_file_mutation,
+            Err(err) => {
+                println!("{err}");
+                return false;
+            }
+        };
+
+        let mut report = VerificationReport::default();
+
+        if let Some(check) = &self.check {
+            (source_file_mutation, report) = self
+                .engine
+                .verify_source_file_mutation(source_file_mutation, check, self.max_repairs)
+                .await;
+        }
+
+        // Only project mutations are resumed.
+        let mutation_folder_path = match target {
+            MutationTarget::Standalone | MutationTarget::Resumed(_) => self
+                .context_processor
+                .save_source_file_mutation(source_file_mutation.clone()),
+            MutationTarget::PlanStep(plan, step) => self
+                .context_processor
+                .save_plan_step_source_file_mutation(plan, step, source_file_mutation.clone()),
+        };
+
+        println!("Mutation saved into {mutation_folder_path}");
+
+        if !report_verification(&report) {
+            println!("Mutation discarded.");
+            return false;
+        }
+
+        match ask_for_confirmation() {
+            true => {
+                let id = target.mutation_id(&source_file_mutation.parent.created_at);
+                let file_path = source_file_mutation.parent.file_path.clone();
+                let instruction = source_file_mutation.parent.prompt.clone();
+
+                let snapshot = match self.commit_snapshot(std::slice::from_ref(&file_path)) {
+                    Ok(snapshot) => snapshot,
+                    Err(err) => {
+                        println!("The mutation can't be committed, it was not applied: {err}");
+                        return false;
+                    }
+                };
+
+                println!("Updated source file to {}", file_path);
+                apply_source_file_mutation(mutation_folder_path, source_file_mutation);
+
+                self.commit_mutation(snapshot, &id, &instruction, &[file_path])
+                    .await;
+                true
+            }
+            false => {
+                println!("Mutation discarded.");
+                false
+            }
+        }
+    }
+
+    /// Returns whether the mutation was applied.
+    pub async fn run_project_mutation(
+        &mut self,
+        mutation_draft: ProjectMutationDraft,
+        target: MutationTarget<'_>,
+    ) -> bool {
+        let id = target.mutation_id(&mutation_draft.created_at);
+
+        // Every stage is saved as soon as it's produced, see `blob resume`.
+        self.context_processor
+            .save_project_mutation_draft(&id, mutation_draft.clone());
+
+        let proposal = match self
+            .engine
+            .generate_structure_proposal(Box::new(mutation_draft))
+            .await
+        {
+            Ok(proposal) => proposal,
+            Err(err) => {
+                println!("{err:#}");
+                return false;
+            }
+        };
+
+        self.context_processor
+            .save_project_mutation_proposal(&id, proposal.as_ref().clone());
+
+        self.run_project_mutation_proposal(proposal, target).await
+    }
+
+    /// Review a proposed structure, then generate and apply its commands.
+    /// Returns whether the mutation was applied.
+    pub async fn run_project_mutation_proposal(
+        &mut self,
+        proposal: Box<ProjectMutationProposed>,
+        target: MutationTarget<'_>,
+    ) -> bool {
+        let project_root_path = proposal.parent.path_root.clone();
+        let id = target.mutation_id(&proposal.parent.created_at);
+
+        let proposal = match review_structure_proposal(proposal) {
+            Some(proposal) => proposal,
+            None => {
+                println!("Mutation discarded.");
+                return false;
+            }
+        };
+
+        self.context_processor
+            .save_project_mutation_proposal(&id, proposal.as_ref().clone());
+
+        let mut mutation_scripted = self.engine.generate_project_mutation(proposal).await;
+
+        let mut report = VerificationReport::default();
+
+        if let Some(check) = &self.check {
+            (mutation_scripted, report) = self
+                .engine
+                .verify_project_mutation(mutation_scripted, check, self.max_repairs)
+                .await;
+        }
+
+        let script_path = match target {
+            MutationTarget::Standalone => self
+                .context_processor
+                .save_project_mutation(mutation_scripted.clone()),
+            MutationTarget::PlanStep(plan, step) => self
+                .context_processor
+                .save_plan_step_project_mutation(plan, step, mutation_scripted.clone()),
+            MutationTarget::Resumed(id) => self
+                .context_processor
+                .save_resumed_project_mutation(id, mutation_scripted.clone()),
+        };
+
+        println!(
+            "Predicted commands:\n{}\n",
+            mutation_scripted.predicted_commands,
+        );
+
+        println!("Script saved into {script_path}");
+
+        if !report_verification(&report) {
+            println!("Mutation discarded.");
+            return false;
+        }
+
+        match ask_for_confirmation() {
+            true => {
+                let instruction = mutation_scripted.parent.parent.prompt.clone();
+
+                let snapshot = match self.commit_snapshot(&[]) {
+                    Ok(snapshot) => snapshot,
+                    Err(err) => {
+                        println!("The mutation can't be committed, it was not applied: {err}");
+                        return false;
+                    }
+                };
+
+                println!("Applying edits to {}", project_root_path);
+                apply_mutation_script(script_path);
+
+                self.commit_mutation(snapshot, &id, &instruction, &[]).await;
+                true
+            }
+            false => {
+                println!("Mutation discarded.");
+                false
+            }
+        }
+    }
+
+    /// Generate every draft, show them as a single diff and apply them together.
+    /// Returns whether the group was applied.
+    pub async fn run_source_file_mutation_group(
+        &mut self,
+        prompt: String,
+        mutation_drafts: Vec<SourceFileMutationDraft>,
+    ) -> bool {
+        let mut mutations = Vec::new();
+
+        for mut mutation_draft in mutation_drafts {
+            println!("Editing {}...", mutation_draft.file_path);
+
+            mutation_draft.context_lines = self.select_context(&mutation_draft);
+
+            match self
+                .engine
+                .transform_specific_file(Box::new(mutation_draft))
+                .await
+            {
+                Ok(source_file_mutation) => mutations.push(source_file_mutation),
+                Err(err) => {
+                    println!("{err}");
+                    return false;
+                }
+            }
+        }
+
+        let group = SourceFileMutationGroup::new(prompt, mutations);
+
+        for mutation in &group.mutations {
+            print!(
+                "{}",
+                unified_file_diff(
+                    &mutation.parent.file_path,
+                    &mutation.current_content,
+                    &mutation.proposed_content
+                )
+            );
+        }
+
+        let (group_path, mutated_file_paths) = self
+            .context_processor
+            .save_source_file_mutation_group(group.clone());
+
+        println!("\nMutation saved into {group_path}");
+
+        match ask_for_confirmation() {
+            true => {
+                let id = mutation_id(&group.created_at);
+                let file_paths: Vec<String> = group
+                    .mutations
+                    .iter()
+                    .map(|mutation| mutation.parent.file_path.clone())
+                    .collect();
+
+                let snapshot = match self.commit_snapshot(&file_paths) {
+                    Ok(snapshot) => snapshot,
+                    Err(err) => {
+                        println!("The mutation can't be committed, it was not applied: {err}");
+                        return false;
+                    }
+                };
+
+                for (mutation, mutated_file_path) in
+                    group.mutations.into_iter().zip(mutated_file_paths)
+                {
+                    println!("Updated source file to {}", mutation.parent.file_path);
+                    apply_source_file_mutation(mutated_file_path, mutation);
+                }
+
+                self.commit_mutation(snapshot, &id, &group.prompt, &file_paths)
+                    .await;
+                true
+            }
+            false => {
+                println!("Mutation discarded.");
+                false
+            }
+        }
+    }
+
+    pub async fn run_plan(&mut self, mut plan: MutationPlan) {
+        loop {
+            println!("Plan:\n{}\n", plan.render());
+
+            match ask_for_review("Do you want to run this plan?") {
+                Review::Approve => break,
+                Review::Edit => {
+                    let edited = match edit_in_editor(&plan.render_for_editing(), "md") {
+                        Ok(edited) => edited,
+                        Err(err) => {
+                            println!("{err:#}, keeping the previous plan.");
+                            continue;
+                        }
+                    };
+
+                    match MutationPlan::parse_steps(&edited) {
+                        Ok(steps) => plan.steps = steps,
+                        Err(err) => println!("{err}, keeping the previous plan."),
+                    }
+                }
+                Review::Reject => {
+                    println!("Plan discarded.");
+                    return;
+                }
+            }
+        }
+
+        let plan_path = self.context_processor.save_mutation_plan(plan.clone());
+
+        println!("Plan saved into {plan_path}");
+
+        for step in plan.steps.clone() {
+            println!("\n{}", step.render());
+
+            let target = MutationTarget::PlanStep(&plan, &step);
+
+            let applied = match &step.kind {
+                MutationPlanStepKind::Structure => {
+                    self.run_project_mutation(step.project_mutation_draft(&plan), target)
+                        .await
+                }
+                MutationPlanStepKind::SourceFile { .. } => {
+                    let mutation_draft = step.source_file_mutation_draft(&plan).unwrap();
+
+                    self.run_source_file_mutation(mutation_draft, target).await
+                }
+            };
+
+            let status = match applied {
+                true => MutationPlanStepStatus::Applied,
+                false => MutationPlanStepStatus::Discarded,
+            };
+
+            plan.set_step_status(step.index, status);
+            self.context_processor.save_mutation_plan(plan.clone());
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::representation::tree::formats::TreeFormat;
 
 #[derive(Parser, Debug)]
 #[command(propagate_version = true)]
@@ -7,6 +9,14 @@ pub struct BlobTool {
     #[arg(short, long)]
     pub root: Option<String>,
 
+    #[arg(long, global = true)]
+    /// List the secrets that were masked before reaching the model.
+    pub show_redactions: bool,
+
+    #[arg(long, global = true, value_enum)]
+    /// How the project structure is written, overrides `[representation]` from the config.
+    pub repr: Option<TreeFormat>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -21,15 +31,285 @@ pub enum Commands {
         /// Reference a unique file in the project, it must be relative to the project root.
         file: Option<String>,
 
+        #[arg(long, conflicts_with = "file")]
+        /// Ask for a step by step plan first, review it and then run each step in order.
+        plan: bool,
+
+        #[arg(long)]
+        /// Command that must pass before the mutation is offered, e.g. "cargo check".
+        /// Overrides `check.command` from `.blob/config.toml`.
+        check: Option<String>,
+
+        #[arg(long)]
+        /// How many times the model may repair a mutation that fails the check.
+        max_repairs: Option<usize>,
+
+        #[arg(long)]
+        /// Commit every applied mutation on the current branch.
+        commit: bool,
+
+        #[arg(long)]
+        /// Commit every applied mutation on a new `blob/<id>` branch.
+        branch: bool,
+
+        #[arg(long)]
+        /// Show which project definitions were given to the model and why.
+        explain_context: bool,
+
+        #[arg(long)]
+        /// Create the file given with `--file` when it doesn't exist yet.
+        create: bool,
+
         #[arg(short, long)]
         /// Accept immediately the mutation.
         /// If not provided, the mutation will be applied only if the user confirms it.
         yes: Option<bool>,
     },
 
+    /// Run a build or lint command and ask the model to fix the reported diagnostics.
+    Fix {
+        /// Command to run, defaults to `check.command` from the config or
+        /// `cargo check --message-format=json`.
+        command: Option<String>,
+
+        #[arg(long)]
+        /// Ignore warnings and notes, only fix errors.
+        errors_only: bool,
+
+        #[arg(long)]
+        /// Commit the fixes on the current branch.
+        commit: bool,
+
+        #[arg(long)]
+        /// Commit the fixes on a new `blob/<id>` branch.
+        branch: bool,
+    },
+
+    /// Reject a mutation that was already applied, reverting the commit created for it.
+    Reject {
+        /// Id of the mutation, the name of its folder in `.blob/.mutations`.
+        id: String,
+    },
+
+    /// Resume a project mutation from its last saved stage, e.g. after the commands
+    /// failed to generate or were rejected.
+    Resume {
+        /// Id of the mutation, the name of its folder in `.blob/.mutations`.
+        id: String,
+
+        #[arg(long, value_enum)]
+        /// Stage to resume from, defaults to the last one saved.
+        from: Option<ResumeStage>,
+
+        #[arg(long)]
+        /// Commit the applied mutation on the current branch.
+        commit: bool,
+
+        #[arg(long)]
+        /// Commit the applied mutation on a new `blob/<id>` branch.
+        branch: bool,
+    },
+
+    /// Ask a question about the project, nothing is modified.
+    Ask {
+        question: String,
+
+        #[arg(long, value_enum, default_value_t = AnswerFormat::Text)]
+        /// Output format of the answer.
+        format: AnswerFormat,
+    },
+
+    /// Review the uncommitted changes, the staged changes or a revision range.
+    Review {
+        #[arg(long, conflicts_with = "range")]
+        /// Review the staged changes instead of the working tree.
+        staged: bool,
+
+        /// A revision (`main`) or revision range (`main..HEAD`) to review.
+        range: Option<String>,
+
+        #[arg(long, value_enum, default_value_t = ReviewFormat::Terminal)]
+        format: ReviewFormat,
+
+        #[arg(long, value_enum, default_value_t = FailOn::Blocking)]
+        /// Exit with a non-zero status when a finding of this severity (or worse) is found.
+        fail_on: FailOn,
+    },
+
+    /// Create the `.blob` workspace, seed definitions and check the model backend.
+    Init {
+        #[arg(long)]
+        /// Add `.blob/.mutations` to `.gitignore` without asking.
+        gitignore: bool,
+
+        #[arg(long, conflicts_with = "gitignore")]
+        /// Leave `.gitignore` untouched without asking.
+        no_gitignore: bool,
+
+        #[arg(long)]
+        /// Don't seed definitions from the README and manifest files.
+        no_seed: bool,
+    },
+
+    /// Propose a commit message for the staged changes.
+    CommitMsg {
+        #[arg(long)]
+        /// Give the project definitions to the model as context.
+        with_definitions: bool,
+
+        #[arg(long)]
+        /// Write the message into `.git/COMMIT_EDITMSG`.
+        write: bool,
+
+        #[arg(long, value_name = "FILE")]
+        /// Prepend the message to FILE, as done by the `prepare-commit-msg` hook.
+        output: Option<String>,
+    },
+
+    /// Manage the git hooks provided by blob.
+    Hooks {
+        #[command(subcommand)]
+        action: HooksAction,
+    },
+
     /// Give a definition related to the project, util to increase the quality of the model predictions.
-    Define { definition: Option<String> },
+    #[command(args_conflicts_with_subcommands = true)]
+    Define {
+        definition: Option<String>,
+
+        #[arg(long)]
+        /// Tag the definition, can be repeated.
+        tag: Vec<String>,
+
+        #[arg(long)]
+        /// Only use the definition for files under a directory or matching a glob.
+        scope: Option<String>,
+
+        #[command(subcommand)]
+        action: Option<DefineAction>,
+    },
+
+    /// Print the project structure exactly as it's given to the model.
+    Tree {
+        /// Folder of the project to show, relative to the project root.
+        path: Option<String>,
+
+        #[arg(long)]
+        /// Deepest level listed, overrides `tree.max_depth` from the config.
+        depth: Option<usize>,
+
+        #[arg(long)]
+        /// List every entry, ignoring the depth and folding limits.
+        all: bool,
+
+        #[arg(long)]
+        /// Follow file names with their size, lines, language and analysis summary.
+        annotate: bool,
+
+        #[arg(long)]
+        /// Report the estimated size of the structure in a prompt.
+        tokens: bool,
+    },
+
+    /// Ask the model for the same structure change with each representation and
+    /// compare how well the proposals read back.
+    EvalRepr {
+        instruction: String,
+
+        #[arg(long = "format", value_enum, value_delimiter = ',')]
+        /// Representations to compare, all of them by default.
+        formats: Vec<TreeFormat>,
+
+        #[arg(long)]
+        /// A path the proposal should contain, can be repeated.
+        expect: Vec<String>,
+
+        #[arg(long, default_value_t = 1)]
+        /// Proposals asked per representation.
+        runs: usize,
+    },
 
     /// Analyze the project and give a report.
     Analyze { file: Option<String> },
 }
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ResumeStage {
+    /// Propose the structure again.
+    Draft,
+    /// Keep the proposed structure and generate the commands again.
+    Proposed,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum AnswerFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ReviewFormat {
+    Terminal,
+    Markdown,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum FailOn {
+    Blocking,
+    Warning,
+    Never,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HooksAction {
+    /// Register `blob commit-msg` as the `prepare-commit-msg` hook.
+    Install {
+        #[arg(long)]
+        /// Replace an existing hook that wasn't installed by blob.
+        force: bool,
+    },
+
+    /// Remove the hooks installed by blob.
+    Uninstall,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DefineAction {
+    /// List the project definitions.
+    List {
+        #[arg(long)]
+        /// Only list the definitions with this tag.
+        tag: Option<String>,
+
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+
+    /// Show the definitions describing blob itself, given to the model when
+    /// `context.include_self_reference` is enabled.
+    SelfReference {
+        #[arg(long)]
+        /// Write the built-in definitions into the project so they can be edited.
+        write: bool,
+
+        #[arg(long, requires = "write")]
+        /// Replace the project's own self-reference definitions.
+        force: bool,
+    },
+
+    /// Remove a definition by id.
+    Rm { id: String },
+
+    /// Edit the text of a definition in `$EDITOR`.
+    Edit { id: String },
+
+    /// Import definitions from a file, one per line or as JSONL records.
+    Import { file: String },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ListFormat {
+    Text,
+    Json,
+}
@@ -0,0 +1,342 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::IsTerminal;
+
+use anyhow::Result;
+
+use crate::representation::tree::formats::TreeFormat;
+use crate::representation::tree::order::natural_cmp;
+use crate::representation::tree::representation::BOX_CHARSET;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    Kept,
+    Added,
+    Removed,
+    Moved,
+}
+
+impl Change {
+    fn marker(&self) -> char {
+        match self {
+            Change::Kept => ' ',
+            Change::Added => '+',
+            Change::Removed => '-',
+            Change::Moved => '~',
+        }
+    }
+
+    /// ANSI colour of the line, green, red and yellow.
+    fn colour(&self) -> Option<&'static str> {
+        match self {
+            Change::Kept => None,
+            Change::Added => Some("32"),
+            Change::Removed => Some("31"),
+            Change::Moved => Some("33"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct DiffNode {
+    is_dir: bool,
+    change: Option<Change>,
+    /// Previous path of a moved entry.
+    moved_from: Option<String>,
+    children: BTreeMap<String, DiffNode>,
+}
+
+impl DiffNode {
+    fn insert(&mut self, path: &str, change: Change, moved_from: Option<String>) {
+        let is_dir = path.ends_with('/');
+        let parts = path
+            .trim_end_matches('/')
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>();
+
+        let mut node = self;
+
+        for (index, part) in parts.iter().enumerate() {
+            let last = index == parts.len() - 1;
+
+            node = node.children.entry(part.to_string()).or_default();
+
+            if !last {
+                node.is_dir = true;
+            }
+        }
+
+        node.is_dir |= is_dir;
+        node.change = Some(change);
+        node.moved_from = moved_from;
+    }
+
+    /// Folders only implied by the path of an entry are shown as kept.
+    fn change(&self) -> Change {
+        self.change.unwrap_or(Change::Kept)
+    }
+}
+
+/// Merge two structures written in `format` into one tree where added, removed and
+/// moved entries are marked with `+`, `-` and `~`. Returns `None` when nothing changed.
+pub fn render_structure_diff(
+    format: TreeFormat,
+    current: &str,
+    proposed: &str,
+    colour: bool,
+) -> Result<Option<String>> {
+    let current = format.parse(current)?;
+    let proposed = format.parse(proposed)?;
+
+    let current_set = current
+        .iter()
+        .map(|path| normalize(path))
+        .collect::<HashSet<_>>();
+    let proposed_set = proposed
+        .iter()
+        .map(|path| normalize(path))
+        .collect::<HashSet<_>>();
+
+    let mut removed = current
+        .iter()
+        .filter(|path| !proposed_set.contains(normalize(path)))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let added = proposed
+        .iter()
+        .filter(|path| !current_set.contains(normalize(path)))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if removed.is_empty() && added.is_empty() {
+        return Ok(None);
+    }
+
+    let mut root = DiffNode::default();
+
+    for path in current.iter().filter(|path| !removed.contains(path)) {
+        root.insert(path, Change::Kept, None);
+    }
+
+    // An entry removed in one place and added with the same name and kind in another
+    // was moved, it's shown once at its new place. Names are only paired when the
+    // folder they were in moved too, or when no other removed or added entry shares
+    // them, otherwise two `lib.rs` could be mistaken for each other.
+    let removed_counts = kind_counts(&removed);
+    let added_counts = kind_counts(&added);
+
+    let unique = added
+        .iter()
+        .filter(|path| {
+            added_counts.get(&kind_of(path)) == Some(&1)
+                && removed_counts.get(&kind_of(path)) == Some(&1)
+        })
+        .cloned()
+        .collect::<HashSet<_>>();
+
+    let mut added = added.clone();
+    added.sort_by_key(|path| normalize(path).matches('/').count());
+
+    // Old and new paths of the moved folders, parents are paired before their children.
+    let mut moves: HashMap<String, String> = HashMap::new();
+
+    for path in added.iter() {
+        let from_moved_parent = moves
+            .iter()
+            .find(|(_, new)| new.as_str() == parent_of(path))
+            .map(|(old, _)| format!("{old}/{}", name_of(path)));
+
+        let index = match from_moved_parent {
+            Some(old_path) => removed
+                .iter()
+                .position(|old| normalize(old) == old_path && kind_of(old) == kind_of(path)),
+            None => None,
+        }
+        .or_else(|| {
+            unique
+                .contains(path)
+                .then(|| removed.iter().position(|old| kind_of(old) == kind_of(path)))
+                .flatten()
+        });
+
+        match index.map(|index| removed.remove(index)) {
+            Some(old) => {
+                moves.insert(normalize(&old).to_string(), normalize(path).to_string());
+                root.insert(path, Change::Moved, Some(normalize(&old).to_string()));
+            }
+            None => root.insert(path, Change::Added, None),
+        }
+    }
+
+    for path in removed.iter() {
+        root.insert(path, Change::Removed, None);
+    }
+
+    let mut lines = vec![];
+    render_children(&root, "", colour, &mut lines);
+
+    Ok(Some(lines.join("\n")))
+}
+
+/// Whether the terminal shows colours, `NO_COLOR` turns them off.
+pub fn use_colour() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+fn normalize(path: &str) -> &str {
+    path.trim_end_matches('/')
+}
+
+fn name_of(path: &str) -> &str {
+    normalize(path).rsplit('/').next().unwrap_or(path)
+}
+
+fn parent_of(path: &str) -> &str {
+    normalize(path)
+        .rsplit_once('/')
+        .map(|(parent, _)| parent)
+        .unwrap_or("")
+}
+
+fn kind_counts(paths: &[String]) -> HashMap<(&str, bool), usize> {
+    let mut counts = HashMap::new();
+
+    for path in paths {
+        *counts.entry(kind_of(path)).or_default() += 1;
+    }
+
+    counts
+}
+
+/// Name of the entry and whether it's a folder.
+fn kind_of(path: &str) -> (&str, bool) {
+    (name_of(path), path.ends_with('/'))
+}
+
+fn render_children(node: &DiffNode, prefix: &str, colour: bool, lines: &mut Vec<String>) {
+    let mut children = node.children.iter().collect::<Vec<_>>();
+
+    children.sort_by(|(a_name, a), (b_name, b)| match (a.is_dir, b.is_dir) {
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        _ => natural_cmp(a_name, b_name),
+    });
+
+    let count = children.len();
+
+    for (index, (name, child)) in children.into_iter().enumerate() {
+        let last = index == count - 1;
+
+        let branch = match last {
+            true => BOX_CHARSET.terminal,
+            false => BOX_CHARSET.branched,
+        };
+
+        let change = child.change();
+
+        let mut label = match child.is_dir {
+            true => format!("{name}/"),
+            false => name.to_string(),
+        };
+
+        if let Some(moved_from) = &child.moved_from {
+            label.push_str(&format!(" (from {moved_from})"));
+        }
+
+        let line = format!("{} {prefix}{branch}{label}", change.marker());
+
+        lines.push(match (colour, change.colour()) {
+            (true, Some(code)) => format!("\x1b[{code}m{line}\x1b[0m"),
+            _ => line,
+        });
+
+        let child_prefix = match last {
+            true => format!("{prefix}{}", BOX_CHARSET.empty),
+            false => format!("{prefix}{}", BOX_CHARSET.vertical),
+        };
+
+        render_children(child, &child_prefix, colour, lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_structure_diff;
+    use crate::representation::tree::formats::TreeFormat;
+
+    fn diff(current: &str, proposed: &str) -> Option<String> {
+        render_structure_diff(TreeFormat::Paths, current, proposed, false).unwrap()
+    }
+
+    #[test]
+    fn nothing_changed() {
+        assert_eq!(diff("src/\nsrc/lib.rs\n", "src/\nsrc/lib.rs/\n"), None);
+    }
+
+    #[test]
+    fn added_and_removed_entries_are_marked() {
+        let lines = diff("a.txt\nb.txt\n", "a.txt\nc/\nc/d.txt\n").unwrap();
+
+        assert_eq!(lines, "+ ├── c/\n+ │   └── d.txt\n  ├── a.txt\n- └── b.txt");
+    }
+
+    #[test]
+    fn children_of_a_moved_folder_move_with_it() {
+        let current = "lib/\nsrc/\nsrc/util/\nsrc/util/mod.rs\nsrc/mod.rs\n";
+        let proposed = "lib/\nlib/util/\nlib/util/mod.rs\nlib/mod.rs\nsrc/\n";
+
+        let lines = diff(current, proposed).unwrap();
+
+        assert_eq!(
+            lines.lines().collect::<Vec<_>>(),
+            [
+                "  ├── lib/",
+                "~ │   ├── util/ (from src/util)",
+                "~ │   │   └── mod.rs (from src/util/mod.rs)",
+                "+ │   └── mod.rs",
+                "  └── src/",
+                "-     └── mod.rs",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_unique_name_is_paired_as_a_move() {
+        let lines = diff("notes.md\nsrc/\n", "src/\nsrc/notes.md\n").unwrap();
+
+        assert_eq!(lines, "  └── src/\n~     └── notes.md (from notes.md)");
+    }
+
+    #[test]
+    fn shared_names_are_not_paired() {
+        let current = "a/\na/mod.rs\nb/\nb/mod.rs\nc/\nd/\n";
+        let proposed = "a/\nb/\nc/\nc/mod.rs\nd/\nd/mod.rs\n";
+
+        let lines = diff(current, proposed).unwrap();
+
+        assert!(!lines.contains('~'), "{lines}");
+        assert_eq!(lines.matches("+ ").count(), 2, "{lines}");
+        assert_eq!(lines.matches("- ").count(), 2, "{lines}");
+    }
+
+    #[test]
+    fn a_file_and_a_folder_with_the_same_name_are_not_paired() {
+        let lines = diff("build\n", "out/\nout/build/\n").unwrap();
+
+        assert!(!lines.contains('~'), "{lines}");
+    }
+
+    #[test]
+    fn colours_wrap_changed_lines_only() {
+        let lines = render_structure_diff(TreeFormat::Paths, "a\nb\n", "a\nc\n", true)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            lines,
+            "  ├── a\n\x1b[31m- ├── b\x1b[0m\n\x1b[32m+ └── c\x1b[0m"
+        );
+    }
+}
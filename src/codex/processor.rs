@@ -1,51 +1,116 @@
 use anyhow::{anyhow, Result};
 use reqwest::{header::HeaderMap, Client};
 use serde_json::{from_str, json};
+use std::path::Path;
+use std::sync::Arc;
 
 use super::codex_responses::{CompletionResponse, EditResponse};
-
-static CODEX_EDIT_API: &str = "https://api.openai.com/v1/engines/code-davinci-edit-001/edits";
-static CODEX_COMPLETION_API: &str = "https://api.openai.com/v1/completions";
+use crate::blob::config::LlmConfig;
+use crate::blob::redaction::Redactor;
 
 #[derive(Debug, Clone)]
 
 pub struct CodexProcessor {
     http_client: Client,
     access_token: String,
+    config: LlmConfig,
+    /// Every outbound text goes through it.
+    redactor: Arc<Redactor>,
 }
 
 impl CodexProcessor {
-    pub fn new(access_token: String) -> Self {
+    pub fn new(access_token: String, config: LlmConfig, redactor: Arc<Redactor>) -> Self {
         Self {
             access_token,
             http_client: Client::new(),
+            config,
+            redactor,
         }
     }
 
-    pub async fn edit_call(
-        self: Self,
-        input: impl Into<String>,
-        instruction: impl Into<String>,
-    ) -> Result<EditResponse> {
-        let endpoint = String::from(CODEX_EDIT_API);
+    fn check_access_token(&self) -> Result<()> {
+        if self.access_token.is_empty() && !self.config.is_local() {
+            return Err(anyhow!("OPENAI_API_KEY is not set"));
+        }
+
+        Ok(())
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}", self.config.base_url.trim_end_matches('/'), path)
+    }
 
+    fn headers(&self) -> HeaderMap {
         let mut headers = HeaderMap::new();
 
-        headers.insert(
-            "Authorization",
-            format!("Bearer {}", self.access_token).parse().unwrap(),
-        );
+        if !self.access_token.is_empty() {
+            headers.insert(
+                "Authorization",
+                format!("Bearer {}", self.access_token).parse().unwrap(),
+            );
+        }
 
         headers.insert("Content-Type", "application/json".parse().unwrap());
 
+        headers
+    }
+
+    /// Ids of the models served by the backend, used to check that it is reachable.
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        self.check_access_token()?;
+
+        let response = self
+            .http_client
+            .get(self.endpoint("models"))
+            .headers(self.headers())
+            .send()
+            .await?;
+
+        let status = response.status();
+        let response_text = response.text().await?;
+
+        if !status.is_success() {
+            return Err(anyhow!(
+                "{} answered {}: {}",
+                self.config.base_url,
+                status,
+                response_text
+            ));
+        }
+
+        let data = from_str::<serde_json::Value>(&response_text)?;
+
+        Ok(data["data"]
+            .as_array()
+            .map(|models| {
+                models
+                    .iter()
+                    .filter_map(|model| model["id"].as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// `source` is the file `input` was read from, env files have every value redacted.
+    pub async fn edit_call(
+        self,
+        input: impl Into<String>,
+        instruction: impl Into<String>,
+        source: Option<&Path>,
+    ) -> Result<EditResponse> {
+        self.check_access_token()?;
+
+        let endpoint = self.endpoint("edits");
+
         let response = self
             .http_client
             .post(&endpoint)
-            .headers(headers)
+            .headers(self.headers())
             .json(&json! {
                 {
-                    "input": input.into(),
-                    "instruction": instruction.into(),
+                    "model": self.config.edit_model,
+                    "input": self.redactor.redact(&input.into(), source),
+                    "instruction": self.redactor.redact(&instruction.into(), None),
                     "temperature": 0.2,
                     "top_p": 1,
                 }
@@ -62,31 +127,22 @@ impl CodexProcessor {
     }
 
     pub async fn completions_call(
-        self: Self,
+        self,
         prompt: impl Into<String>,
         stop_words: Option<Vec<String>>,
     ) -> Result<CompletionResponse> {
-        let endpoint = String::from(CODEX_COMPLETION_API);
-
-        let mut headers = HeaderMap::new();
+        self.check_access_token()?;
 
-        headers.insert(
-            "Authorization",
-            format!("Bearer {}", self.access_token).parse().unwrap(),
-        );
-
-        headers.insert("Content-Type", "application/json".parse().unwrap());
-
-        let model_name = "text-davinci-003"; // "code-davinci-002"; // "text-davinci-003"
+        let endpoint = self.endpoint("completions");
 
         let response = self
             .http_client
             .post(&endpoint)
-            .headers(headers)
+            .headers(self.headers())
             .json(&json! {
                 {
-                    "model": model_name,
-                    "prompt": prompt.into(),
+                    "model": self.config.completion_model,
+                    "prompt": self.redactor.redact(&prompt.into(), None),
                     "max_tokens": 1000,
                     "temperature": 0.2,
                     "stop": stop_words,
@@ -105,7 +161,6 @@ impl CodexProcessor {
         let Ok(data) = from_str::<CompletionResponse>(&response_text) else {
             // let response_text = response_text;
             return Err(anyhow!(response_text));
-
         };
 
         Ok(data)
@@ -1,71 +1,126 @@
+use std::collections::BTreeMap;
 use std::fs::read_to_string;
-use std::path::PathBuf;
+use std::path::Path;
+use std::sync::Arc;
+
+use futures::StreamExt;
 
 use crate::blob::analysis::{
-    ProjectAnalysisDraft, ProjectAnalysisResult, ProjectSourceFileAnalysis,
+    one_line_summary, relative_file_path, ProjectAnalysisDraft, ProjectAnalysisResult,
+    ProjectSourceFileAnalysis,
 };
+use crate::blob::config::LlmConfig;
+use crate::blob::definitions::context_lines_for;
 use crate::blob::mutation::{
     ProjectMutation, ProjectMutationDraft, ProjectMutationProposed, SourceFileMutation,
     SourceFileMutationDraft,
 };
+use crate::blob::plan::MutationPlan;
+use crate::blob::question::{ProjectAnswer, ProjectQuestion};
+use crate::blob::redaction::Redactor;
+use crate::blob::review::{CodeReview, ReviewComment};
+use crate::blob::verification::{CheckCommand, CheckOutcome, VerificationReport};
 use crate::codex::processor::CodexProcessor;
-use crate::llm::templates::interpretation_prompt_template;
-use crate::representation::tree::iterator::Event;
-use crate::representation::{
-    tree::iterator::{TreeIter, TreeProcessor},
-    tree::representation::TreeRepresentation,
+use crate::llm::templates::{
+    ask_answer_prompt_template, ask_selection_prompt_template, commit_message_prompt_template,
+    interpretation_prompt_template, plan_prompt_template, repair_file_instruction_template,
+    repair_script_prompt_template, review_prompt_template, source_file_instruction_template,
+};
+use crate::representation::tree::iterator::{EntryKind, Event};
+use crate::representation::tree::{
+    annotations::Annotations, formats::TreeFormat, iterator::TreeIter,
 };
+use crate::structure::software::FileDiff;
 
 pub struct LLMEngine {
-    llm_representation: TreeRepresentation,
-    // walker: TreeFileWalker,
+    representation: TreeFormat,
+    annotations: Option<Arc<Annotations>>,
     codex_processor: CodexProcessor,
+    redactor: Arc<Redactor>,
 }
 
 impl LLMEngine {
-    pub fn new() -> Self {
-        let access_token = std::env::var("OPENAI_API_KEY").unwrap();
+    pub fn new(config: &LlmConfig, redactor: Arc<Redactor>) -> Self {
+        // Commands that never reach the model shouldn't require a key,
+        // the processor reports the missing key on the first call instead.
+        let access_token = std::env::var("OPENAI_API_KEY").unwrap_or_default();
 
         LLMEngine {
-            llm_representation: TreeRepresentation::new(),
+            representation: TreeFormat::default(),
+            annotations: None,
             // walker: ,
-            codex_processor: CodexProcessor::new(access_token),
+            codex_processor: CodexProcessor::new(access_token, config.clone(), redactor.clone()),
+            redactor,
         }
     }
 
+    /// Models served by the configured backend.
+    pub async fn available_models(&self) -> anyhow::Result<Vec<String>> {
+        self.codex_processor.list_models().await
+    }
+
+    /// Format of the project structure in every prompt, see `[representation]`.
+    pub fn with_representation(mut self, format: TreeFormat) -> Self {
+        self.representation = format;
+        self
+    }
+
+    /// Annotate the files of the structure in every prompt, see `[representation]`.
+    pub fn with_annotations(mut self, annotations: Option<Annotations>) -> Self {
+        self.annotations = annotations.map(Arc::new);
+        self
+    }
+
     fn generate_context(&mut self, root: &mut TreeIter) -> String {
-        self.llm_representation.construct(root).unwrap()
+        self.representation
+            .processor(self.annotations.clone())
+            .construct(root)
+            .unwrap()
     }
 
-    pub async fn generate_structure_proposal(
+    /// Current structure in `format` and the structure the model proposes for the
+    /// instruction of the draft, in the same format.
+    pub async fn propose_structure(
         &mut self,
-        mut mutation_draft: Box<ProjectMutationDraft>,
-    ) -> Box<ProjectMutationProposed> {
-        let mut root_tree = mutation_draft.tree_iter();
-        let context = self.generate_context(root_tree.as_mut());
-
-        let prompt = mutation_draft.prompt.clone();
+        mutation_draft: &mut ProjectMutationDraft,
+        format: TreeFormat,
+    ) -> anyhow::Result<(String, String)> {
+        let mut root_tree = mutation_draft.tree_iter()?;
+        let context = format
+            .processor(self.annotations.clone())
+            .construct(root_tree.as_mut())
+            .unwrap();
 
         let edit = self
             .codex_processor
             .clone()
-            .edit_call(context.clone(), prompt.clone())
-            .await
-            .unwrap();
+            .edit_call(context.clone(), mutation_draft.prompt.clone(), None)
+            .await?;
+
+        Ok((context, edit.choices.first().unwrap().text.clone()))
+    }
+
+    pub async fn generate_structure_proposal(
+        &mut self,
+        mut mutation_draft: Box<ProjectMutationDraft>,
+    ) -> anyhow::Result<Box<ProjectMutationProposed>> {
+        let (context, proposal) = self
+            .propose_structure(mutation_draft.as_mut(), self.representation)
+            .await?;
 
-        Box::new(ProjectMutationProposed::new_from_parent(
+        Ok(Box::new(ProjectMutationProposed::new_from_parent(
             mutation_draft,
-            context.clone(),
-            edit.choices.first().unwrap().text.clone(),
-        ))
+            context,
+            proposal,
+            self.representation,
+        )))
     }
 
+    /// Shell commands turning the current structure into the proposed one.
     pub async fn generate_project_mutation(
         &mut self,
-        mutation_draft: Box<ProjectMutationDraft>,
+        snapshot: Box<ProjectMutationProposed>,
     ) -> ProjectMutation {
-        let snapshot = self.generate_structure_proposal(mutation_draft).await;
-
         let next_prompt = snapshot.clone().generate_prompt().unwrap();
 
         let completion = self
@@ -82,92 +137,490 @@ impl LLMEngine {
         ProjectMutation::new_from_parent(snapshot.clone(), predicted_commands, full_script)
     }
 
+    pub async fn generate_mutation_plan(
+        &mut self,
+        mut mutation_draft: Box<ProjectMutationDraft>,
+    ) -> anyhow::Result<MutationPlan> {
+        let mut root_tree = mutation_draft.tree_iter()?;
+        let context = self.generate_context(root_tree.as_mut());
+
+        let context_definitions = mutation_draft
+            .context_lines
+            .clone()
+            .unwrap_or_default()
+            .join("\n");
+
+        let prompt = plan_prompt_template(
+            &mutation_draft.path_root,
+            &context,
+            &context_definitions,
+            &mutation_draft.prompt,
+        );
+
+        let completion = self
+            .codex_processor
+            .clone()
+            .completions_call(prompt, None)
+            .await
+            .unwrap();
+
+        let raw_plan = completion.choices.first().unwrap().text.clone();
+
+        let steps = MutationPlan::parse_steps(&raw_plan).map_err(|err| {
+            anyhow::anyhow!("The model's plan couldn't be read ({err}):\n{raw_plan}")
+        })?;
+
+        Ok(MutationPlan::new_from_parent(mutation_draft, steps))
+    }
+
     pub async fn transform_specific_file(
         &mut self,
         mutation_draft: Box<SourceFileMutationDraft>,
-    ) -> SourceFileMutation {
+    ) -> anyhow::Result<SourceFileMutation> {
         // let mut root_tree = mutation_draft.tree_iter();
         // let context = self.generate_context(root_tree.as_mut());
 
-        let prompt = mutation_draft.prompt.clone();
+        let prompt =
+            source_file_instruction_template(&mutation_draft.prompt, &mutation_draft.context_lines);
         // mutation_draft.
         // let file_path = format!("{}/{}", project_path.clone(), file.clone());
-        let file_content = std::fs::read_to_string(mutation_draft.file_path.clone()).unwrap();
+        // A missing file is only treated as empty when the draft asks for its creation.
+        let file_content = match std::fs::read_to_string(&mutation_draft.file_path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && mutation_draft.create => {
+                String::new()
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(anyhow::anyhow!(
+                    "{} doesn't exist, pass --create to create it",
+                    mutation_draft.file_path
+                ));
+            }
+            Err(err) => {
+                return Err(anyhow::anyhow!(
+                    "{} couldn't be read: {err}",
+                    mutation_draft.file_path
+                ));
+            }
+        };
+
+        // A redacted file would be written back with its placeholders.
+        let secrets = self
+            .redactor
+            .detect(&file_content, Some(Path::new(&mutation_draft.file_path)));
+
+        if !secrets.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{} contains secrets ({}), refusing to send it to the model",
+                mutation_draft.file_path,
+                secrets.join(", ")
+            ));
+        }
 
         let edit = self
             .codex_processor
             .clone()
-            .edit_call(file_content.clone(), prompt)
+            .edit_call(
+                file_content.clone(),
+                prompt,
+                Some(Path::new(&mutation_draft.file_path)),
+            )
             .await
             .unwrap();
         // self.generate_bash_script(Box::new(mutation_draft)).await
 
-        SourceFileMutation::new_from_parent(
+        Ok(SourceFileMutation::new_from_parent(
             mutation_draft,
             file_content,
             edit.choices.first().unwrap().text.clone(),
+        ))
+    }
+
+    pub async fn repair_source_file_mutation(
+        &mut self,
+        mutation: SourceFileMutation,
+        failure: &CheckOutcome,
+    ) -> SourceFileMutation {
+        let instruction = repair_file_instruction_template(
+            &mutation.parent.prompt,
+            &failure.command,
+            &failure.excerpt(),
+        );
+
+        let edit = self
+            .codex_processor
+            .clone()
+            .edit_call(
+                mutation.proposed_content.clone(),
+                instruction,
+                Some(Path::new(&mutation.parent.file_path)),
+            )
+            .await
+            .unwrap();
+
+        SourceFileMutation::new_from_parent(
+            mutation.parent,
+            mutation.current_content,
+            edit.choices.first().unwrap().text.clone(),
         )
     }
 
+    pub async fn repair_project_mutation(
+        &mut self,
+        mutation: ProjectMutation,
+        failure: &CheckOutcome,
+    ) -> ProjectMutation {
+        let path_root = mutation.parent.parent.path_root.clone();
+
+        let prompt = repair_script_prompt_template(
+            &mutation.full_script,
+            &path_root,
+            &failure.command,
+            &failure.excerpt(),
+        );
+
+        let completion = self
+            .codex_processor
+            .clone()
+            .completions_call(prompt, None)
+            .await
+            .unwrap();
+
+        let predicted_commands = completion.choices.first().unwrap().text.clone();
+
+        // Keep the original header (context, structures and `cd`) and swap in the repaired commands.
+        let script_header = mutation
+            .full_script
+            .strip_suffix(&mutation.predicted_commands)
+            .unwrap_or(&mutation.full_script)
+            .to_string();
+
+        let full_script = format!("{}{}", script_header, predicted_commands);
+
+        ProjectMutation::new_from_parent(mutation.parent, predicted_commands, full_script)
+    }
+
+    /// Run `check` against the mutation and ask for repairs until it passes or
+    /// `max_repairs` attempts are exhausted.
+    pub async fn verify_source_file_mutation(
+        &mut self,
+        mut mutation: SourceFileMutation,
+        check: &CheckCommand,
+        max_repairs: usize,
+    ) -> (SourceFileMutation, VerificationReport) {
+        let mut report = VerificationReport::default();
+
+        loop {
+            println!("Running `{}`...", check.command);
+
+            let outcome = match check.check_source_file_mutation(&mutation) {
+                Ok(outcome) => outcome,
+                Err(e) => CheckOutcome {
+                    command: check.command.clone(),
+                    success: false,
+                    output: e.to_string(),
+                },
+            };
+
+            report.attempts.push(outcome.clone());
+
+            if outcome.success || report.attempts.len() > max_repairs {
+                return (mutation, report);
+            }
+
+            println!(
+                "Check failed, asking for a repair ({}/{})",
+                report.attempts.len(),
+                max_repairs
+            );
+
+            mutation = self.repair_source_file_mutation(mutation, &outcome).await;
+        }
+    }
+
+    pub async fn verify_project_mutation(
+        &mut self,
+        mut mutation: ProjectMutation,
+        check: &CheckCommand,
+        max_repairs: usize,
+    ) -> (ProjectMutation, VerificationReport) {
+        let mut report = VerificationReport::default();
+
+        loop {
+            println!("Running `{}` in a scratch copy...", check.command);
+
+            let outcome = match check.check_project_mutation(&mutation) {
+                Ok(outcome) => outcome,
+                Err(e) => CheckOutcome {
+                    command: check.command.clone(),
+                    success: false,
+                    output: e.to_string(),
+                },
+            };
+
+            report.attempts.push(outcome.clone());
+
+            if outcome.success || report.attempts.len() > max_repairs {
+                return (mutation, report);
+            }
+
+            println!(
+                "Check failed, asking for a repair ({}/{})",
+                report.attempts.len(),
+                max_repairs
+            );
+
+            mutation = self.repair_project_mutation(mutation, &outcome).await;
+        }
+    }
+
+    /// Pick the files relevant to the question, then answer from their content.
+    /// `select_context` gives the definitions for the files picked to answer, the
+    /// files are only known after a first call to the model, which gets the unscoped
+    /// ones.
+    pub async fn answer_question(
+        &mut self,
+        mut question: Box<ProjectQuestion>,
+        summaries: BTreeMap<String, String>,
+        select_context: impl Fn(&[String]) -> Vec<String>,
+    ) -> anyhow::Result<ProjectAnswer> {
+        let mut root_tree = question.structure_tree_iter()?;
+        let context = self.generate_context(root_tree.as_mut());
+
+        let project_files: Vec<String> = question
+            .tree_iter()
+            .filter_map(|event| match event {
+                Ok(Event::File(f)) => Some(relative_file_path(
+                    &question.path_root,
+                    &f.path().to_string_lossy(),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        let summaries_block = summaries
+            .iter()
+            .filter(|(file, _)| project_files.contains(file))
+            .map(|(file, summary)| format!("{}: {}", file, one_line_summary(summary)))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let context_definitions = select_context(&[]).join("\n");
+
+        let selection_prompt = ask_selection_prompt_template(
+            &context,
+            &summaries_block,
+            &context_definitions,
+            &question.question,
+        );
+
+        let completion = self
+            .codex_processor
+            .clone()
+            .completions_call(selection_prompt, None)
+            .await
+            .unwrap();
+
+        let mut files: Vec<String> = Vec::new();
+
+        for line in completion.choices.first().unwrap().text.lines() {
+            let candidate = line
+                .trim()
+                .trim_start_matches(|c: char| c == '-' || c == '*' || c.is_ascii_digit())
+                .trim_start_matches('.')
+                .trim()
+                .trim_matches('`')
+                .trim_start_matches("./");
+
+            if project_files.iter().any(|file| file == candidate)
+                && !files.iter().any(|file| file == candidate)
+            {
+                files.push(candidate.to_string());
+            }
+        }
+
+        files.truncate(5);
+
+        if files.is_empty() {
+            files = lexical_file_selection(&question.question, &project_files, &summaries, 3);
+        }
+
+        let files_block = files
+            .iter()
+            .map(|file| {
+                let path = Path::new(&question.path_root).join(file);
+
+                // Redacted before numbering, env files are only recognized by their path
+                // and their `KEY=value` lines at the start of a line. Redaction keeps
+                // the line breaks, so the numbers still match the file.
+                let content = self
+                    .redactor
+                    .redact(&read_to_string(&path).unwrap_or_default(), Some(&path));
+
+                let numbered = content
+                    .lines()
+                    .enumerate()
+                    .map(|(i, line)| format!("{:>5} | {}", i + 1, line))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+
+                let max_char = 10_000;
+
+                let upper = numbered
+                    .char_indices()
+                    .nth(max_char)
+                    .map(|(i, _)| i)
+                    .unwrap_or(numbered.len());
+
+                format!("# {}\n```\n{}\n```", file, &numbered[..upper])
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n");
+
+        let context_lines = select_context(&files);
+
+        let answer_prompt =
+            ask_answer_prompt_template(&files_block, &context_lines.join("\n"), &question.question);
+
+        question.context_lines = Some(context_lines);
+
+        let completion = self
+            .codex_processor
+            .clone()
+            .completions_call(answer_prompt, None)
+            .await
+            .unwrap();
+
+        let answer = completion.choices.first().unwrap().text.trim().to_string();
+
+        Ok(ProjectAnswer::new_from_parent(question, files, answer))
+    }
+
+    pub async fn generate_commit_message(
+        &mut self,
+        instruction: Option<&str>,
+        diff: &str,
+        context_lines: Vec<String>,
+    ) -> anyhow::Result<String> {
+        let prompt = commit_message_prompt_template(instruction, diff, &context_lines.join("\n"));
+
+        let completion = self
+            .codex_processor
+            .clone()
+            .completions_call(prompt, None)
+            .await?;
+
+        let message = completion
+            .choices
+            .first()
+            .map(|choice| choice.text.trim().to_string())
+            .unwrap_or_default();
+
+        Ok(message)
+    }
+
+    /// Review every file diff on its own, with the definitions selected for it, and
+    /// collect the findings.
+    pub async fn review_changes(
+        &mut self,
+        target: String,
+        file_diffs: Vec<(FileDiff, Vec<String>)>,
+    ) -> anyhow::Result<CodeReview> {
+        let mut comments = Vec::new();
+
+        for (file_diff, context_lines) in file_diffs {
+            eprintln!("Reviewing {}...", file_diff.path);
+
+            let prompt = review_prompt_template(
+                &file_diff.path,
+                &file_diff.status,
+                &file_diff.truncated_patch(8_000),
+                &context_lines.join("\n"),
+            );
+
+            // A file left out would read as a file without findings.
+            let completion = self
+                .codex_processor
+                .clone()
+                .completions_call(prompt, None)
+                .await
+                .map_err(|err| anyhow::anyhow!("{} couldn't be reviewed: {err}", file_diff.path))?;
+
+            let text = completion
+                .choices
+                .first()
+                .map(|choice| choice.text.clone())
+                .unwrap_or_default();
+
+            comments.append(&mut ReviewComment::parse_all(&file_diff.path, &text));
+        }
+
+        Ok(CodeReview::new(target, comments))
+    }
+
     pub async fn generate_recursive_analysis(
         &mut self,
         mut project_analysis_draft: Box<ProjectAnalysisDraft>,
         //
     ) -> ProjectAnalysisResult {
-        let iter = project_analysis_draft.tree_iter();
+        // Folders are still being read while the first files are analyzed.
+        let mut events = project_analysis_draft.tree_iter().into_stream();
 
         let prompt = project_analysis_draft.prompt.clone();
 
-        let prompts: Vec<(Option<PathBuf>, String)> = iter
-            .map(|event| -> (Option<PathBuf>, String) {
-                match event {
-                    Ok(event) => match event {
-                        Event::OpenDir(dir) => {
-                            println!("Dir: {}", dir.path().display());
-
-                            (None, "".to_string())
-                        }
-                        Event::File(f) => {
-                            let path = f.path().to_path_buf();
-
-                            println!("File: {}", f.path().display());
-
-                            let file_content = read_to_string(f.path()).unwrap_or("".to_string());
-
-                            let max_char = 10_000;
-
-                            let upper = if max_char > file_content.len() {
-                                file_content.len()
-                            } else {
-                                max_char
-                            };
-
-                            let final_prompt = interpretation_prompt_template(
-                                f.path(),
-                                file_content.get(..upper).unwrap().to_string(),
-                                prompt.clone(),
-                            );
-
-                            (Some(path), final_prompt) // format!("{} {}", file_content.get(..upper).unwrap(), prompt),
-                        }
-                        _ => (None, "".to_string()),
-                    },
-                    Err(e) => {
-                        println!("Error: {}", e);
-                        (None, "".to_string())
-                    }
+        let mut source_code_analysis: Vec<ProjectSourceFileAnalysis> = Vec::new();
+
+        while let Some(event) = events.next().await {
+            let f = match event {
+                Ok(Event::OpenDir(dir)) => {
+                    println!("Dir: {}", dir.path().display());
+                    continue;
                 }
-            })
-            .filter(|el| if el.0 == None { false } else { true })
-            .collect();
+                // Submodules and links that aren't followed have no content of their own.
+                Ok(Event::File(f)) if f.kind() == EntryKind::File => f,
+                Ok(_) => continue,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    continue;
+                }
+            };
 
-        let mut source_code_analysis: Vec<ProjectSourceFileAnalysis> = Vec::new();
+            println!("File: {}", f.path().display());
+
+            let file_content = read_to_string(f.path()).unwrap_or("".to_string());
+
+            let max_char = 10_000;
+
+            let upper = if max_char > file_content.len() {
+                file_content.len()
+            } else {
+                max_char
+            };
+
+            let context_lines = context_lines_for(
+                &project_analysis_draft.definitions,
+                Some(&relative_file_path(
+                    &project_analysis_draft.path_root,
+                    &f.path().to_string_lossy(),
+                )),
+            );
+
+            // Redacted here too, so env files are recognized by their name.
+            let file_content = self
+                .redactor
+                .redact(file_content.get(..upper).unwrap(), Some(f.path()));
+
+            let final_prompt = interpretation_prompt_template(
+                f.path(),
+                file_content,
+                prompt.clone(),
+                &context_lines,
+            );
 
-        for (file_name, prompt) in prompts {
             let completion_response = self
                 .codex_processor
                 .clone()
-                .completions_call(prompt.clone(), Some(vec!["#".to_string()]))
+                .completions_call(final_prompt.clone(), Some(vec!["#".to_string()]))
                 .await;
 
             let (interpretation, error) = match completion_response.as_ref() {
@@ -181,16 +634,9 @@ impl LLMEngine {
                 }
             };
 
-            let file_path = file_name
-                .clone()
-                .unwrap()
-                .as_path()
-                .to_string_lossy()
-                .to_string();
-
             source_code_analysis.push(ProjectSourceFileAnalysis {
-                file_path,
-                prompt: prompt.clone(),
+                file_path: f.path().to_string_lossy().to_string(),
+                prompt: final_prompt,
                 result: interpretation,
                 error,
             })
@@ -202,3 +648,45 @@ impl LLMEngine {
         }
     }
 }
+
+/// Fallback used when the model doesn't name any existing file: score every file by
+/// how many words of the question appear in its path or summary.
+fn lexical_file_selection(
+    question: &str,
+    project_files: &[String],
+    summaries: &BTreeMap<String, String>,
+    limit: usize,
+) -> Vec<String> {
+    let words: Vec<String> = question
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.len() >= 3)
+        .map(|word| word.to_lowercase())
+        .collect();
+
+    let mut scored: Vec<(usize, &String)> = project_files
+        .iter()
+        .map(|file| {
+            let haystack = format!(
+                "{} {}",
+                file.to_lowercase(),
+                summaries
+                    .get(file)
+                    .map(|summary| summary.to_lowercase())
+                    .unwrap_or_default()
+            );
+
+            let score = words.iter().filter(|word| haystack.contains(*word)).count();
+
+            (score, file)
+        })
+        .filter(|(score, _)| *score > 0)
+        .collect();
+
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, file)| file.clone())
+        .collect()
+}
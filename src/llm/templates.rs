@@ -1,9 +1,19 @@
 use std::path::Path;
 
-pub fn interpretation_prompt_template(file: &Path, file_source: String, prompt: String) -> String {
+pub fn interpretation_prompt_template(
+    file: &Path,
+    file_source: String,
+    prompt: String,
+    context_lines: &[String],
+) -> String {
+    let context = context_lines
+        .iter()
+        .map(|line| format!("    Project context: {line}\n"))
+        .collect::<String>();
+
     format!(
         "
-    # {}
+{}    # {}
     ```
     {}
     ```
@@ -11,8 +21,217 @@ pub fn interpretation_prompt_template(file: &Path, file_source: String, prompt:
     {}:
 
     ",
+        context,
         file.display(),
         file_source,
         prompt
     )
 }
+
+pub fn plan_prompt_template(
+    project_root: &str,
+    structure: &str,
+    context_definitions: &str,
+    instruction: &str,
+) -> String {
+    format!(
+        "You are planning changes to a software project.
+
+Project context:
+{}
+
+Current structure of {}:
+{}
+
+Instruction:
+{}
+
+Break the instruction down into a short numbered list of small, ordered steps.
+Each step is exactly one line and is either a change to the file structure or an edit to a single file:
+N. [structure] <what to create, move or delete>
+N. [file <path relative to the project root>] <what to change in that file>
+
+Plan:
+",
+        context_definitions.trim_end(),
+        project_root,
+        structure.trim_end(),
+        instruction.trim_end(),
+    )
+}
+
+pub fn repair_file_instruction_template(instruction: &str, check: &str, output: &str) -> String {
+    format!(
+        "{}\n\nThe result fails `{}` with the following output, fix the errors:\n{}",
+        instruction.trim_end(),
+        check,
+        output.trim_end(),
+    )
+}
+
+pub fn repair_script_prompt_template(
+    full_script: &str,
+    project_root: &str,
+    check: &str,
+    output: &str,
+) -> String {
+    format!(
+        "{}
+
+# The commands above fail `{}` with the following output:
+# {}
+
+# Corrected unix commands to perform the same transformation:
+cd {}
+",
+        full_script.trim_end(),
+        check,
+        output.trim_end().replace('\n', "\n# "),
+        project_root,
+    )
+}
+
+/// Instruction given to the edit model, with the definitions that apply to the file.
+pub fn source_file_instruction_template(instruction: &str, context_lines: &[String]) -> String {
+    if context_lines.is_empty() {
+        return instruction.to_string();
+    }
+
+    format!(
+        "{}\n\nKeep in mind the following about this project:\n{}",
+        instruction.trim_end(),
+        context_lines
+            .iter()
+            .map(|line| format!("- {line}"))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    )
+}
+
+pub fn fix_diagnostics_instruction_template(file: &str, diagnostics: &str) -> String {
+    format!(
+        "Fix the following diagnostics reported for {}, keep the rest of the file unchanged:\n{}",
+        file,
+        diagnostics.trim_end(),
+    )
+}
+
+pub fn ask_selection_prompt_template(
+    structure: &str,
+    summaries: &str,
+    context_definitions: &str,
+    question: &str,
+) -> String {
+    format!(
+        "Project context:
+{}
+
+Project structure:
+{}
+
+File summaries:
+{}
+
+Question:
+{}
+
+List the paths of the files (at most 5) that are most likely needed to answer the question.
+Write one path per line, exactly as it appears in the structure, relative to the project root.
+
+Files:
+",
+        context_definitions.trim_end(),
+        structure.trim_end(),
+        summaries.trim_end(),
+        question.trim_end(),
+    )
+}
+
+pub fn ask_answer_prompt_template(
+    files: &str,
+    context_definitions: &str,
+    question: &str,
+) -> String {
+    format!(
+        "Project context:
+{}
+
+Relevant files, every line is prefixed with its line number:
+{}
+
+Question:
+{}
+
+Answer the question using only the files above. Cite the code you rely on as `path:line` or
+`path:start-end`, using the paths and line numbers shown above. If the files don't contain the
+answer, say so.
+
+Answer:
+",
+        context_definitions.trim_end(),
+        files.trim_end(),
+        question.trim_end(),
+    )
+}
+
+pub fn review_prompt_template(
+    file: &str,
+    status: &str,
+    patch: &str,
+    context_definitions: &str,
+) -> String {
+    format!(
+        "You are reviewing a change to a software project.
+
+Project context:
+{}
+
+Diff of {} ({}), every line starts with its line number in the new version of the file:
+{}
+
+Review the change for bugs, security problems, missing error handling and unclear code.
+Write one finding per line as `LINE | SEVERITY | comment`, where LINE is a line number from
+the diff (or `-` for the whole file) and SEVERITY is one of info, warning or blocking.
+Use blocking only for problems that must be fixed before merging. Write nothing if the change looks good.
+
+Findings:
+",
+        context_definitions.trim_end(),
+        file,
+        status,
+        patch.trim_end(),
+    )
+}
+
+pub fn commit_message_prompt_template(
+    instruction: Option<&str>,
+    diff: &str,
+    context_definitions: &str,
+) -> String {
+    let instruction = match instruction {
+        Some(instruction) => format!(
+            "The change was requested with this instruction:\n{}\n\n",
+            instruction.trim_end()
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        "Project context:
+{}
+
+{}Diff:
+{}
+
+Write a commit message for this change in the conventional commits style: a subject line
+`type(optional scope): summary` of at most 72 characters, using one of feat, fix, refactor,
+docs, test, chore, perf, style, build or ci, then a blank line and a short body explaining what
+changed and why. Write only the message.
+
+Commit message:
+",
+        context_definitions.trim_end(),
+        instruction,
+        diff.trim_end(),
+    )
+}
@@ -1,16 +1,36 @@
+use std::collections::BTreeSet;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use blob::analysis::ProjectAnalysisDraft;
+use blob::config::{BlobConfig, CommitMode};
 use blob::context::BlobContextProcessor;
-use blob::mutation::{ProjectMutationDraft, SourceFileMutation, SourceFileMutationDraft};
-use clap::Parser;
-use cli::tool::{BlobTool, Commands};
+use blob::definitions::{parse_import, BlobDefinition};
+use blob::diagnostics::{group_by_file, parse_diagnostics, Diagnostic};
+use blob::evaluation::{render_evaluation, ProposalCheck, RepresentationEvaluation};
+use blob::mutation::{ProjectMutationDraft, ProjectMutationStage, SourceFileMutationDraft};
+use blob::question::ProjectQuestion;
+use blob::ranking::estimate_tokens;
+use blob::redaction::Redactor;
+use blob::review::ReviewSeverity;
+use blob::verification::CheckCommand;
+use blob::workspace::{ensure_gitignore_entry, seed_definitions, tree_iter_from};
+use clap::{Parser, ValueEnum};
+use cli::editor::edit_in_editor;
+use cli::runner::{ask_yes_no, MutationRunner, MutationTarget};
+use cli::tool::{
+    AnswerFormat, BlobTool, Commands, DefineAction, FailOn, HooksAction, ListFormat, ResumeStage,
+    ReviewFormat,
+};
 use dotenv::dotenv;
 use llm::engine::LLMEngine;
-use structure::software::Project;
+use llm::templates::fix_diagnostics_instruction_template;
+use representation::tree::folding::TreeLimits;
+use representation::tree::formats::TreeFormat;
+use structure::hooks::{install_prepare_commit_msg, uninstall_prepare_commit_msg};
+use structure::software::{DiffTarget, Project};
 
 mod blob;
 mod cli;
@@ -19,44 +39,6 @@ mod llm;
 mod representation;
 pub mod structure;
 
-fn ask_for_confirmation() -> bool {
-    println!("Do you want to apply this mutation? (y/N):");
-
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
-
-    match input.trim() {
-        "y" | "yes" => true,
-        _ => false,
-    }
-}
-
-fn apply_source_file_mutation(
-    mutation_folder_path: String,
-    source_file_mutation: SourceFileMutation,
-) {
-    let res = Command::new("cp")
-        .arg("-r")
-        .arg(mutation_folder_path)
-        .arg(source_file_mutation.parent.file_path)
-        .output()
-        .unwrap();
-
-    let output = String::from_utf8_lossy(&res.stdout);
-
-    println!("{}", output);
-}
-
-fn apply_mutation_script(mutation_script_path: String) {
-    let res = Command::new("bash")
-        .arg(mutation_script_path)
-        .output()
-        .unwrap();
-    let output = String::from_utf8_lossy(&res.stdout);
-
-    println!("{}", output);
-}
-
 #[tokio::main]
 async fn main() {
     dotenv().ok();
@@ -65,91 +47,857 @@ async fn main() {
 
     let project_root_path = cli.root.unwrap_or(".".to_string());
 
-    let mut engine = LLMEngine::new();
+    let config = match BlobConfig::load(&project_root_path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err:#}");
+            std::process::exit(2);
+        }
+    };
+
+    let redactor = match Redactor::new(&config.redaction) {
+        Ok(redactor) => Arc::new(redactor),
+        Err(err) => {
+            eprintln!("{err:#}");
+            std::process::exit(2);
+        }
+    };
+
+    let representation = cli
+        .repr
+        .unwrap_or(config.representation.format_for(&config.llm.edit_model));
+
     let context_processor = BlobContextProcessor::new(project_root_path.clone());
 
+    let annotations = config
+        .representation
+        .annotate
+        .then(|| context_processor.tree_annotations(config.filters.max_file_size));
+
+    let mut engine = LLMEngine::new(&config.llm, redactor.clone())
+        .with_representation(representation)
+        .with_annotations(annotations);
+
     match &cli.command {
         Commands::Do {
             instruction,
             file,
+            plan,
+            check,
+            max_repairs,
+            commit,
+            branch,
+            explain_context,
+            create,
             yes: _,
-        } => match file {
-            Some(file) => {
-                let mutation_draft = Box::new(SourceFileMutationDraft::new(
-                    file.clone(),
-                    instruction.clone().unwrap(),
-                ));
-
-                let source_file_mutation = engine.transform_specific_file(mutation_draft).await;
-
-                let mutation_folder_path =
-                    context_processor.save_source_file_mutation(source_file_mutation.clone());
-
-                println!("Mutation saved into {mutation_folder_path}");
-
-                match ask_for_confirmation() {
-                    true => {
-                        println!(
-                            "Updated source file to {}",
-                            source_file_mutation.clone().parent.file_path
-                        );
-                        apply_source_file_mutation(mutation_folder_path, source_file_mutation)
+        } => {
+            let mut runner = MutationRunner {
+                engine: &mut engine,
+                context_processor: &context_processor,
+                check: CheckCommand::from_config(
+                    project_root_path.clone(),
+                    &config.check,
+                    check.clone(),
+                ),
+                max_repairs: max_repairs.unwrap_or(config.check.max_repairs),
+                project_root: project_root_path.clone(),
+                commit_mode: CommitMode::from_flags(*commit, *branch, config.git.commit),
+                context: config.context.clone(),
+                explain_context: *explain_context,
+            };
+
+            match file {
+                Some(file) => {
+                    let mutation_draft =
+                        SourceFileMutationDraft::new(file.clone(), instruction.clone().unwrap())
+                            .with_creation(*create);
+
+                    runner
+                        .run_source_file_mutation(mutation_draft, MutationTarget::Standalone)
+                        .await;
+                }
+                None => {
+                    // Scoped definitions are picked up by the file mutations of a plan.
+                    let selection = context_processor.select_project_context(
+                        instruction.as_deref().unwrap_or_default(),
+                        None,
+                        &config.context,
+                    );
+
+                    if *explain_context {
+                        println!("{}", selection.render_explanation());
+                    }
+
+                    let context_lines = selection.context_lines();
+
+                    let mutation = ProjectMutationDraft::new(
+                        project_root_path.clone(),
+                        instruction.clone().unwrap(),
+                        context_lines,
+                    );
+
+                    if *plan {
+                        let mutation_plan = match runner
+                            .engine
+                            .generate_mutation_plan(Box::new(mutation))
+                            .await
+                        {
+                            Ok(mutation_plan) => mutation_plan,
+                            Err(err) => {
+                                eprintln!("{err}");
+                                std::process::exit(1);
+                            }
+                        };
+
+                        runner.run_plan(mutation_plan).await;
+                    } else {
+                        runner
+                            .run_project_mutation(mutation, MutationTarget::Standalone)
+                            .await;
                     }
-                    false => println!("Mutation discarded."),
                 }
             }
-            None => {
-                let definitions = context_processor
-                    .retrieve_definitions(blob::context::BlobDefinitionKind::Project);
+        }
+        Commands::Fix {
+            command,
+            errors_only,
+            commit,
+            branch,
+        } => {
+            let command = command
+                .clone()
+                .or_else(|| config.check.command.clone())
+                .unwrap_or_else(|| "cargo check --message-format=json".to_string());
+
+            let check = CheckCommand::new(
+                project_root_path.clone(),
+                command.clone(),
+                config.check.workspace,
+            );
+
+            println!("Running `{command}`...");
+
+            let outcome = check.run().unwrap();
+
+            let (diagnostics, outside): (Vec<Diagnostic>, Vec<Diagnostic>) =
+                parse_diagnostics(&outcome.output)
+                    .into_iter()
+                    .filter(|diagnostic| !errors_only || diagnostic.level == "error")
+                    .partition(|diagnostic| diagnostic.project_file(&project_root_path).is_some());
+
+            if !outside.is_empty() {
+                let files: BTreeSet<String> = outside
+                    .into_iter()
+                    .map(|diagnostic| diagnostic.file)
+                    .collect();
 
-                // let mut self_definitions = context_processor
-                //     .retrieve_definitions(blob::context::BlobDefinitionKind::SelfReference);
+                println!(
+                    "Skipping diagnostics of files that aren't part of the project: {}",
+                    files.into_iter().collect::<Vec<String>>().join(", ")
+                );
+            }
 
-                // definitions.append(&mut self_definitions);
+            if diagnostics.is_empty() {
+                match outcome.success {
+                    true => println!("Nothing to fix."),
+                    false => println!(
+                        "`{command}` failed but no diagnostics could be parsed:\n{}",
+                        outcome.excerpt()
+                    ),
+                }
+                return;
+            }
 
-                let context_lines = definitions
-                    .iter()
-                    .map(|def| def.definition.clone())
-                    .collect();
+            let groups = group_by_file(diagnostics);
 
-                let mutation = ProjectMutationDraft::new(
-                    project_root_path.clone(),
-                    instruction.clone().unwrap(),
-                    context_lines,
-                );
+            println!(
+                "Found diagnostics in {} file(s): {}",
+                groups.len(),
+                groups.keys().cloned().collect::<Vec<String>>().join(", ")
+            );
 
-                let mutation_scripted = engine.generate_project_mutation(Box::new(mutation)).await;
+            let mutation_drafts = groups
+                .iter()
+                .map(|(file, diagnostics)| {
+                    let rendered = diagnostics
+                        .iter()
+                        .map(|diagnostic| diagnostic.render())
+                        .collect::<Vec<String>>()
+                        .join("\n\n");
+
+                    SourceFileMutationDraft::new(
+                        Path::new(&project_root_path)
+                            .join(file)
+                            .display()
+                            .to_string(),
+                        fix_diagnostics_instruction_template(file, &rendered),
+                    )
+                })
+                .collect();
 
-                let script_path =
-                    context_processor.save_project_mutation(mutation_scripted.clone());
+            let mut runner = MutationRunner {
+                engine: &mut engine,
+                context_processor: &context_processor,
+                check: None,
+                max_repairs: 0,
+                project_root: project_root_path.clone(),
+                commit_mode: CommitMode::from_flags(*commit, *branch, config.git.commit),
+                context: config.context.clone(),
+                explain_context: false,
+            };
+
+            let applied = runner
+                .run_source_file_mutation_group(format!("blob fix: {command}"), mutation_drafts)
+                .await;
 
-                println!(
-                    "Predicted commands:\n{}\n",
-                    mutation_scripted.predicted_commands,
+            if applied {
+                let remaining = check
+                    .run()
+                    .map(|outcome| parse_diagnostics(&outcome.output).len())
+                    .unwrap_or_default();
+
+                println!("`{command}` now reports {remaining} diagnostic(s).");
+            }
+        }
+        Commands::Ask { question, format } => {
+            let summaries = context_processor
+                .retrieve_project_analysis()
+                .map(|analysis| analysis.summaries())
+                .unwrap_or_default();
+
+            let project_question =
+                ProjectQuestion::new(project_root_path.clone(), question.clone());
+
+            let select_context = |files: &[String]| {
+                context_processor
+                    .select_context_for_paths(question, files, &config.context)
+                    .context_lines()
+            };
+
+            let answer = match engine
+                .answer_question(Box::new(project_question), summaries, select_context)
+                .await
+            {
+                Ok(answer) => answer,
+                Err(err) => {
+                    eprintln!("{err:#}");
+                    std::process::exit(2);
+                }
+            };
+
+            match format {
+                AnswerFormat::Text => {
+                    println!("{}", answer.answer);
+                    eprintln!("\nFiles consulted: {}", answer.files.join(", "));
+                }
+                AnswerFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&answer).unwrap());
+                }
+            }
+        }
+        Commands::Review {
+            staged,
+            range,
+            format,
+            fail_on,
+        } => {
+            let project = match Project::new(PathBuf::from(&project_root_path)) {
+                Ok(project) => project,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(2);
+                }
+            };
+
+            let (target, label) = match (staged, range) {
+                (true, _) => (DiffTarget::Staged, "staged changes".to_string()),
+                (false, Some(range)) => (DiffTarget::Range(range.clone()), range.clone()),
+                (false, None) => (DiffTarget::WorkingTree, "working tree".to_string()),
+            };
+
+            // blob's own workspace is not part of the change under review.
+            let file_diffs: Vec<_> = match project.diff(&target, 10) {
+                Ok(file_diffs) => file_diffs
+                    .into_iter()
+                    .filter(|file_diff| !file_diff.path.starts_with(".blob/"))
+                    .collect(),
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(2);
+                }
+            };
+
+            if file_diffs.is_empty() {
+                eprintln!("Nothing to review in the {label}.");
+                return;
+            }
+
+            // Each file is reviewed with the definitions scoped to it, ranked against
+            // its diff.
+            let file_diffs = file_diffs
+                .into_iter()
+                .map(|file_diff| {
+                    let context_lines = context_processor
+                        .select_project_context(
+                            &file_diff.patch,
+                            Some(&file_diff.path),
+                            &config.context,
+                        )
+                        .context_lines();
+
+                    (file_diff, context_lines)
+                })
+                .collect();
+
+            let review = match engine.review_changes(label, file_diffs).await {
+                Ok(review) => review,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(2);
+                }
+            };
+
+            match format {
+                ReviewFormat::Terminal => print!("{}", review.render_terminal()),
+                ReviewFormat::Markdown => print!("{}", review.render_markdown()),
+                ReviewFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&review).unwrap())
+                }
+            }
+
+            let threshold = match fail_on {
+                FailOn::Blocking => Some(ReviewSeverity::Blocking),
+                FailOn::Warning => Some(ReviewSeverity::Warning),
+                FailOn::Never => None,
+            };
+
+            if threshold.is_some_and(|severity| review.has_findings_at_least(severity)) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Reject { id } => {
+            let Some(metadata) = context_processor.retrieve_mutation_metadata(id) else {
+                eprintln!("Unknown mutation '{id}'");
+                std::process::exit(2);
+            };
+
+            let Some(mut commit) = metadata.commit else {
+                println!("Mutation {id} was not committed, nothing to revert.");
+                return;
+            };
+
+            if let Some(reverted_by) = &commit.reverted_by {
+                println!("Mutation {id} was already reverted by {reverted_by}.");
+                return;
+            }
+
+            let project = match Project::new(PathBuf::from(&project_root_path)) {
+                Ok(project) => project,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(2);
+                }
+            };
+
+            let message = format!(
+                "Revert blob mutation {id}\n\nThis reverts commit {}.\n\nBlob-Mutation-Id: {id}\n",
+                commit.commit_id
+            );
+
+            match project.revert_commit(&commit.commit_id, commit.branch.as_deref(), &message) {
+                Ok(revert_id) => {
+                    println!("Reverted {} with {}", commit.commit_id, revert_id);
+
+                    commit.reverted_by = Some(revert_id);
+                    context_processor.record_mutation_commit(id, commit);
+                }
+                Err(err) => {
+                    eprintln!("Failed to revert {}: {err}", commit.commit_id);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Resume {
+            id,
+            from,
+            commit,
+            branch,
+        } => {
+            let Some(metadata) = context_processor.retrieve_mutation_metadata(id) else {
+                eprintln!("Unknown mutation '{id}'");
+                std::process::exit(2);
+            };
+
+            if let Some(commit) = metadata
+                .commit
+                .as_ref()
+                .filter(|commit| commit.reverted_by.is_none())
+            {
+                eprintln!(
+                    "Mutation {id} was already applied in {}, reject it first with `blob reject {id}`.",
+                    commit.commit_id
                 );
+                std::process::exit(2);
+            }
+
+            let Some(stage) = metadata.mutation.project_stage() else {
+                eprintln!("Mutation {id} isn't a project mutation, only those can be resumed.");
+                std::process::exit(2);
+            };
+
+            let stage = match (stage, from) {
+                (stage, Some(ResumeStage::Draft)) => {
+                    ProjectMutationStage::Drafted(stage.into_draft())
+                }
+                (ProjectMutationStage::Drafted(_), Some(ResumeStage::Proposed)) => {
+                    eprintln!("Mutation {id} has no proposed structure yet, resume it with `--from draft`.");
+                    std::process::exit(2);
+                }
+                (stage, _) => stage,
+            };
+
+            let mut runner = MutationRunner {
+                engine: &mut engine,
+                context_processor: &context_processor,
+                check: CheckCommand::from_config(project_root_path.clone(), &config.check, None),
+                max_repairs: config.check.max_repairs,
+                project_root: project_root_path.clone(),
+                commit_mode: CommitMode::from_flags(*commit, *branch, config.git.commit),
+                context: config.context.clone(),
+                explain_context: false,
+            };
+
+            match stage {
+                ProjectMutationStage::Drafted(draft) => {
+                    println!("Resuming {id} from its draft: {}", draft.prompt);
+
+                    runner
+                        .run_project_mutation(*draft, MutationTarget::Resumed(id))
+                        .await;
+                }
+                ProjectMutationStage::Proposed(proposal) => {
+                    println!(
+                        "Resuming {id} from its proposed structure: {}",
+                        proposal.parent.prompt
+                    );
+
+                    runner
+                        .run_project_mutation_proposal(proposal, MutationTarget::Resumed(id))
+                        .await;
+                }
+            }
+        }
+        Commands::Init {
+            gitignore,
+            no_gitignore,
+            no_seed,
+        } => {
+            for path in context_processor.init_workspace() {
+                println!("Created {path}");
+            }
+
+            if BlobConfig::write_template(&project_root_path).unwrap() {
+                println!("Created {}", BlobConfig::path(&project_root_path));
+            }
+
+            if !*no_seed {
+                let author = context_processor.definition_author();
+
+                let definitions = seed_definitions(&project_root_path)
+                    .into_iter()
+                    .map(|text| {
+                        BlobDefinition::new(text, vec!["init".to_string()], None, author.clone())
+                    })
+                    .collect();
+
+                match context_processor.import_project_definitions(definitions) {
+                    Ok(added) => {
+                        for definition in added {
+                            println!("Defined {}", definition.render());
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("{err}");
+                        std::process::exit(1);
+                    }
+                }
+            }
 
-                println!("Script saved into {script_path}");
+            let add_to_gitignore = match (gitignore, no_gitignore) {
+                (true, _) => true,
+                (_, true) => false,
+                _ => ask_yes_no("Add .blob/.mutations to .gitignore?"),
+            };
 
-                // match yes.unwrap_or(ask_for_confirmation()) {
-                match ask_for_confirmation() {
-                    true => {
-                        println!("Applying edits to {}", project_root_path.clone());
-                        apply_mutation_script(script_path);
+            if add_to_gitignore
+                && ensure_gitignore_entry(&project_root_path, ".blob/.mutations").unwrap()
+            {
+                println!("Added .blob/.mutations to .gitignore");
+            }
+
+            if config.llm.is_local() {
+                println!("Using the local backend at {}", config.llm.base_url);
+            }
+
+            match engine.available_models().await {
+                Ok(models) => {
+                    println!(
+                        "{} is reachable ({} models).",
+                        config.llm.base_url,
+                        models.len()
+                    );
+
+                    for model in [&config.llm.completion_model, &config.llm.edit_model] {
+                        if !models.is_empty() && !models.contains(model) {
+                            println!("Warning: the backend doesn't list the model {model}.");
+                        }
                     }
-                    false => println!("Mutation discarded."),
+                }
+                Err(err) => {
+                    println!("Warning: the model backend isn't usable yet: {err}");
+                    println!("Set OPENAI_API_KEY or point `llm.base_url` in .blob/config.toml to a local server.");
+                }
+            }
+        }
+        Commands::CommitMsg {
+            with_definitions,
+            write,
+            output,
+        } => {
+            let project = match Project::new(PathBuf::from(&project_root_path)) {
+                Ok(project) => project,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(2);
+                }
+            };
+
+            let diff = project.staged_patch(8_000).unwrap();
+
+            if diff.trim().is_empty() {
+                eprintln!("Nothing staged.");
+                // Don't break `git commit` when the hook runs without staged changes.
+                std::process::exit(if output.is_some() { 0 } else { 1 });
+            }
+
+            let context_lines = match with_definitions {
+                true => {
+                    let staged_paths = project
+                        .diff(&DiffTarget::Staged, 0)
+                        .unwrap()
+                        .into_iter()
+                        .map(|file_diff| file_diff.path)
+                        .collect::<Vec<_>>();
+
+                    context_processor
+                        .select_context_for_paths(&diff, &staged_paths, &config.context)
+                        .context_lines()
+                }
+                false => vec![],
+            };
+
+            let message = match engine
+                .generate_commit_message(None, &diff, context_lines)
+                .await
+            {
+                Ok(message) => message,
+                Err(err) => {
+                    eprintln!("Failed to generate the commit message: {err}");
+                    std::process::exit(1);
+                }
+            };
+
+            if *write {
+                let path = project.git_dir().join("COMMIT_EDITMSG");
+                std::fs::write(&path, format!("{message}\n")).unwrap();
+                eprintln!("Message written into {}", path.display());
+            }
+
+            if let Some(output) = output {
+                // Keep whatever git already put in the file (comments, status).
+                let existing = std::fs::read_to_string(output).unwrap_or_default();
+                std::fs::write(output, format!("{message}\n{existing}")).unwrap();
+            }
+
+            if !*write && output.is_none() {
+                println!("{message}");
+            }
+        }
+        Commands::Hooks { action } => {
+            let project = match Project::new(PathBuf::from(&project_root_path)) {
+                Ok(project) => project,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(2);
+                }
+            };
+
+            match action {
+                HooksAction::Install { force } => {
+                    match install_prepare_commit_msg(&project, *force) {
+                        Ok(path) => println!("Installed {}", path.display()),
+                        Err(err) => {
+                            eprintln!("{err}");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                HooksAction::Uninstall => match uninstall_prepare_commit_msg(&project).unwrap() {
+                    Some(path) => println!("Removed {}", path.display()),
+                    None => println!("No hook installed by blob."),
+                },
+            }
+        }
+        Commands::Define {
+            definition,
+            tag,
+            scope,
+            action,
+        } => match action {
+            None => match definition {
+                Some(definition) => {
+                    let added = match context_processor.save_project_definitions(
+                        vec![definition.clone()],
+                        tag.clone(),
+                        scope.clone(),
+                    ) {
+                        Ok(added) => added,
+                        Err(err) => {
+                            eprintln!("{err}");
+                            std::process::exit(1);
+                        }
+                    };
+
+                    for definition in added {
+                        println!("Defined {}", definition.id);
+                    }
+                }
+                None => {
+                    eprintln!("Give a definition or use one of the define subcommands.");
+                    std::process::exit(2);
+                }
+            },
+            Some(DefineAction::List { tag, format }) => {
+                let definitions = context_processor
+                    .retrieve_definitions(blob::context::BlobDefinitionKind::Project)
+                    .into_iter()
+                    .filter(|def| tag.as_ref().is_none_or(|tag| def.tags.contains(tag)))
+                    .collect::<Vec<_>>();
+
+                match format {
+                    ListFormat::Text => {
+                        for definition in definitions {
+                            println!("{}", definition.render());
+                        }
+                    }
+                    ListFormat::Json => {
+                        println!("{}", serde_json::to_string_pretty(&definitions).unwrap());
+                    }
+                }
+            }
+            Some(DefineAction::SelfReference { write, force }) => {
+                if *write {
+                    match context_processor.write_self_reference_definitions(*force) {
+                        Ok(Some(path)) => {
+                            println!("Self-reference definitions written into {path}")
+                        }
+                        Ok(None) => {
+                            eprintln!(
+                                "The project already has its own self-reference definitions, use --force to replace them."
+                            );
+                            std::process::exit(1);
+                        }
+                        Err(err) => {
+                            eprintln!("{err}");
+                            std::process::exit(1);
+                        }
+                    }
+
+                    return;
+                }
+
+                for definition in context_processor.retrieve_self_reference_definitions() {
+                    println!("{}", definition.render());
                 }
             }
+            Some(DefineAction::Rm { id }) => {
+                match context_processor.remove_project_definition(id) {
+                    Ok(removed) => println!("Removed {}", removed.render()),
+                    Err(err) => {
+                        eprintln!("{err}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            Some(DefineAction::Edit { id }) => {
+                let mut definition = match context_processor.find_project_definition(id) {
+                    Ok(definition) => definition,
+                    Err(err) => {
+                        eprintln!("{err}");
+                        std::process::exit(1);
+                    }
+                };
+
+                let edited = edit_in_editor(&format!("{}\n", definition.text), "txt").unwrap();
+                let edited = edited.trim();
+
+                if edited.is_empty() || edited == definition.text {
+                    println!("Definition left unchanged.");
+                    return;
+                }
+
+                definition.text = edited.to_string();
+
+                if let Err(err) = context_processor.update_project_definition(definition.clone()) {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
+
+                println!("Updated {}", definition.render());
+            }
+            Some(DefineAction::Import { file }) => {
+                let contents = match std::fs::read_to_string(file) {
+                    Ok(contents) => contents,
+                    Err(err) => {
+                        eprintln!("Failed to read {file}: {err}");
+                        std::process::exit(1);
+                    }
+                };
+
+                let definitions = parse_import(&contents, context_processor.definition_author());
+
+                let imported = match context_processor.import_project_definitions(definitions) {
+                    Ok(imported) => imported,
+                    Err(err) => {
+                        eprintln!("{err}");
+                        std::process::exit(1);
+                    }
+                };
+
+                println!("Imported {} definitions.", imported.len());
+            }
         },
-        Commands::Define { definition } => {
-            context_processor.save_project_definitions(vec![definition.clone().unwrap()]);
+        Commands::Tree {
+            path,
+            depth,
+            all,
+            annotate,
+            tokens,
+        } => {
+            let start = match path {
+                Some(path) => PathBuf::from(&project_root_path).join(path),
+                None => PathBuf::from(&project_root_path),
+            };
+
+            let tree = match tree_iter_from(&project_root_path, &start) {
+                Ok(tree) => tree,
+                Err(err) => {
+                    eprintln!("{err:#}");
+                    std::process::exit(1);
+                }
+            };
+
+            let mut limits = config.tree.limits();
+
+            if let Some(depth) = depth {
+                limits.max_depth = Some(*depth);
+            }
+
+            if *all {
+                limits = TreeLimits::default();
+            }
+
+            let mut tree = tree.with_limits(limits).unwrap();
+
+            let annotations = (*annotate || config.representation.annotate).then(|| {
+                Arc::new(context_processor.tree_annotations(config.filters.max_file_size))
+            });
+
+            let structure = representation
+                .processor(annotations)
+                .construct(&mut tree)
+                .unwrap();
+
+            // The prompts leave the root out, `tree` starts with it.
+            if matches!(representation, TreeFormat::Tree | TreeFormat::Ascii) {
+                println!("{}", path.as_deref().unwrap_or("."));
+            }
+
+            print!("{structure}");
+
+            if *tokens {
+                println!(
+                    "\n~{} tokens ({} characters) as {representation}",
+                    estimate_tokens(&structure),
+                    structure.chars().count()
+                );
+            }
         }
-        Commands::Analyze { file } => {
+        Commands::EvalRepr {
+            instruction,
+            formats,
+            expect,
+            runs,
+        } => {
+            let formats = match formats.is_empty() {
+                true => TreeFormat::value_variants().to_vec(),
+                false => formats.clone(),
+            };
+
+            let mut evaluations = vec![];
+
+            for format in formats {
+                let mut checks = vec![];
+                let mut tokens = 0;
+
+                for run in 1..=*runs {
+                    println!("Asking with the {format} representation ({run}/{runs})...");
+
+                    let mut draft = ProjectMutationDraft::new(
+                        project_root_path.clone(),
+                        instruction.clone(),
+                        vec![],
+                    );
+
+                    let (context, proposal) =
+                        match engine.propose_structure(&mut draft, format).await {
+                            Ok(structures) => structures,
+                            Err(err) => {
+                                eprintln!("{err:#}");
+                                std::process::exit(2);
+                            }
+                        };
+
+                    // Read back with the same parser so only the proposal is judged.
+                    let current = format.parse(&context).unwrap_or_default();
+                    tokens = estimate_tokens(&context);
+
+                    checks.push(ProposalCheck::new(
+                        &current,
+                        format.parse(&proposal).ok(),
+                        expect,
+                    ));
+                }
+
+                evaluations.push(RepresentationEvaluation {
+                    format,
+                    tokens,
+                    checks,
+                });
+            }
+
+            println!(
+                "\n{}",
+                render_evaluation(&config.llm.edit_model, &mut evaluations)
+            );
+        }
+        Commands::Analyze { file: _ } => {
             // let definitions =
             //     context_processor.retrieve_definitions(blob::context::BlobDefinitionKind::Project);
 
             // let context_lines = definitions
             //     .iter()
-            //     .map(|def| def.definition.clone())
+            //     .map(|def| def.text.clone())
             //     .collect();
 
             // let analysis = engine.analyze_project(context_lines).await;
@@ -167,7 +915,11 @@ async fn main() {
             // data.iter().flatten();
 
             // println!("Data: {:?}", data);
-            let analysis = ProjectAnalysisDraft::new_with_default_prompt(project_root_path.clone());
+            let mut analysis =
+                ProjectAnalysisDraft::new_with_default_prompt(project_root_path.clone());
+
+            analysis.definitions =
+                context_processor.retrieve_definitions(blob::context::BlobDefinitionKind::Project);
 
             let result = engine
                 .generate_recursive_analysis(Box::new(analysis.clone()))
@@ -192,6 +944,12 @@ async fn main() {
             // save document content to file
             let mut file = File::create("analysis_full.md").unwrap();
             file.write_all(document_content.as_bytes()).unwrap();
+
+            context_processor.save_project_analysis(result);
         }
     }
+
+    if cli.show_redactions {
+        eprintln!("{}", redactor.render_report());
+    }
 }
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use super::iterator::{Entry, EntryKind, LinkState};
+
+/// Separates a name from its annotation, parsers drop everything after it.
+pub const ANNOTATION_SEPARATOR: &str = "  # ";
+
+/// Size, line count, language and analysis summary of the files of a tree, e.g.
+/// `main.rs  # 1.2 KB, 45 lines, Rust: Entry point of the CLI.`
+pub struct Annotations {
+    root: PathBuf,
+    /// One-line summaries from `blob analyze`, keyed by relative path.
+    summaries: BTreeMap<String, String>,
+    /// Bigger files get no line count.
+    max_size: u64,
+}
+
+impl Annotations {
+    pub fn new(root: PathBuf, summaries: BTreeMap<String, String>, max_size: u64) -> Self {
+        Annotations {
+            root,
+            summaries,
+            max_size,
+        }
+    }
+
+    /// Size, lines, language and summary of a file, nothing for the other entries.
+    fn facts(&self, entry: &Entry) -> (Vec<String>, Option<&String>) {
+        if entry.kind() != EntryKind::File {
+            return (vec![], None);
+        }
+
+        let path = entry.path();
+        let mut facts = vec![human_size(entry.size())];
+
+        if let Some(lines) = count_lines(path, self.max_size) {
+            facts.push(match lines {
+                1 => "1 line".to_string(),
+                lines => format!("{lines} lines"),
+            });
+        }
+
+        if let Some(language) = detect_language(path) {
+            facts.push(language.to_string());
+        }
+
+        let relative = path
+            .strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        (facts, self.summaries.get(&relative))
+    }
+}
+
+/// Lines of the file like [`str::lines`] counts them, read in chunks and never past
+/// `max_size` bytes. Files above the limit are skipped.
+fn count_lines(path: &Path, max_size: u64) -> Option<usize> {
+    let file = File::open(path).ok()?;
+
+    if file.metadata().ok()?.len() > max_size {
+        return None;
+    }
+
+    let mut reader = BufReader::new(file.take(max_size));
+    let mut lines = 0;
+    let mut last = b'\n';
+
+    loop {
+        let chunk = reader.fill_buf().ok()?;
+
+        let Some(&end) = chunk.last() else {
+            break;
+        };
+
+        lines += chunk.iter().filter(|byte| **byte == b'\n').count();
+        last = end;
+
+        let length = chunk.len();
+        reader.consume(length);
+    }
+
+    // A last line without a newline still counts.
+    Some(lines + usize::from(last != b'\n'))
+}
+
+/// Text appended to the name of `entry`: what kind of link or submodule it is and,
+/// with annotations, the facts of files. Empty for plain folders.
+pub fn suffix(annotations: Option<&Annotations>, entry: &Entry) -> String {
+    note(annotations, entry)
+        .map(|note| format!("{ANNOTATION_SEPARATOR}{note}"))
+        .unwrap_or_default()
+}
+
+/// What [`suffix`] says about `entry`, without the separator.
+pub fn note(annotations: Option<&Annotations>, entry: &Entry) -> Option<String> {
+    let mut facts = entry.link().map(|link| {
+        let target = link.target.display();
+
+        match link.state {
+            LinkState::Shown | LinkState::Followed => format!("symlink to {target}"),
+            LinkState::Broken => format!("broken symlink to {target}"),
+            LinkState::Cycle => format!("symlink to {target}, not followed (cycle)"),
+            LinkState::Outside => {
+                format!("symlink to {target}, not followed (outside the tree)")
+            }
+        }
+    });
+
+    if entry.kind() == EntryKind::Submodule {
+        facts = Some("git submodule".to_string());
+    }
+
+    let (file_facts, summary) = annotations
+        .map(|annotations| annotations.facts(entry))
+        .unwrap_or_default();
+
+    let facts = facts.into_iter().chain(file_facts).collect::<Vec<_>>();
+
+    if facts.is_empty() {
+        return None;
+    }
+
+    let mut note = facts.join(", ");
+
+    if let Some(summary) = summary {
+        note.push_str(&format!(": {summary}"));
+    }
+
+    Some(note)
+}
+
+/// Name of `entry` in a structure, submodules end with `/` so they read as folders.
+pub fn leaf_name(entry: &Entry) -> String {
+    let name = entry
+        .path()
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    match entry.kind() {
+        EntryKind::Submodule => format!("{name}/"),
+        _ => name.to_string(),
+    }
+}
+
+/// Drop the annotation of a line written with [`Annotations`].
+pub fn strip_annotation(line: &str) -> &str {
+    match line.split_once(ANNOTATION_SEPARATOR) {
+        Some((name, _)) => name,
+        None => line,
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Language (or file format) of a file from its name, `None` when unknown.
+pub fn detect_language(path: &Path) -> Option<&'static str> {
+    let name = path.file_name()?.to_string_lossy();
+
+    let by_name = match name.as_ref() {
+        "Dockerfile" => Some("Dockerfile"),
+        "Makefile" | "makefile" | "GNUmakefile" => Some("Makefile"),
+        "CMakeLists.txt" => Some("CMake"),
+        "Rakefile" | "Gemfile" => Some("Ruby"),
+        _ => None,
+    };
+
+    if by_name.is_some() {
+        return by_name;
+    }
+
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+
+    let language = match extension.as_str() {
+        "rs" => "Rust",
+        "py" | "pyi" => "Python",
+        "js" | "mjs" | "cjs" => "JavaScript",
+        "jsx" => "JavaScript (JSX)",
+        "ts" | "mts" | "cts" => "TypeScript",
+        "tsx" => "TypeScript (TSX)",
+        "go" => "Go",
+        "java" => "Java",
+        "kt" | "kts" => "Kotlin",
+        "scala" => "Scala",
+        "swift" => "Swift",
+        "c" | "h" => "C",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" => "C++",
+        "cs" => "C#",
+        "rb" => "Ruby",
+        "php" => "PHP",
+        "ex" | "exs" => "Elixir",
+        "erl" => "Erlang",
+        "hs" => "Haskell",
+        "ml" | "mli" => "OCaml",
+        "clj" | "cljs" => "Clojure",
+        "lua" => "Lua",
+        "dart" => "Dart",
+        "zig" => "Zig",
+        "sh" | "bash" | "zsh" => "Shell",
+        "ps1" => "PowerShell",
+        "sql" => "SQL",
+        "html" | "htm" => "HTML",
+        "css" => "CSS",
+        "scss" | "sass" => "Sass",
+        "vue" => "Vue",
+        "svelte" => "Svelte",
+        "md" | "markdown" => "Markdown",
+        "json" => "JSON",
+        "yaml" | "yml" => "YAML",
+        "toml" => "TOML",
+        "xml" => "XML",
+        "proto" => "Protocol Buffers",
+        "graphql" | "gql" => "GraphQL",
+        "tf" => "Terraform",
+        _ => return None,
+    };
+
+    Some(language)
+}
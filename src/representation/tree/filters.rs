@@ -1,27 +1,34 @@
 //! Various file filters and abstractions for working with them.
 
-extern crate git2;
-
-use self::git2::Repository;
+use git2::{Config, Repository};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
+use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::result;
+use std::sync::Mutex;
 
-type Result = result::Result<bool, Box<dyn Error>>;
+type Result = result::Result<bool, Box<dyn Error + Send + Sync>>;
 
-pub trait FileFilter {
+/// Filters are shared by every folder read of a walk, possibly from several threads.
+pub trait FileFilter: Send + Sync {
     fn filter(&self, path: &Path) -> Result;
 }
 
 impl<F> FileFilter for F
 where
-    F: Fn(&Path) -> Result,
+    F: Fn(&Path) -> Result + Send + Sync,
 {
     fn filter(&self, path: &Path) -> Result {
         (self)(path)
     }
 }
 
+#[derive(Default)]
 pub struct FilterAggregate {
     filters: Vec<Box<dyn FileFilter>>,
 }
@@ -36,18 +43,10 @@ impl FilterAggregate {
     }
 }
 
-impl Default for FilterAggregate {
-    fn default() -> Self {
-        FilterAggregate {
-            filters: Vec::new(),
-        }
-    }
-}
-
 impl FileFilter for FilterAggregate {
     fn filter(&self, path: &Path) -> Result {
         for f in &self.filters {
-            if !f.filter(path).unwrap() {
+            if !f.filter(path)? {
                 return Ok(false);
             }
         }
@@ -55,34 +54,259 @@ impl FileFilter for FilterAggregate {
     }
 }
 
-pub struct GitignoreFilter {
-    repo: Repository,
+/// Ignore-file matcher in pure Rust, it works with or without a git repository.
+///
+/// Every `.gitignore` from the repository root (or from `root` when there is no
+/// repository) down to the filtered path applies, deeper files take precedence,
+/// then `.git/info/exclude` and the global `core.excludesFile` are honored. The
+/// `.git` folder itself is always skipped.
+pub struct IgnoreFileFilter {
+    /// Topmost folder whose ignore files apply.
+    base: PathBuf,
+    file_name: &'static str,
+    /// Ignore files that apply to the whole repository, by precedence.
+    excludes: Vec<Gitignore>,
+    matchers: Mutex<HashMap<PathBuf, Option<Gitignore>>>,
 }
 
-impl GitignoreFilter {
-    /// Create a new filter rooted at `path`.
-    pub fn new(path: PathBuf) -> Option<result::Result<GitignoreFilter, Box<dyn Error>>> {
-        let result = Repository::discover(path).map(|repo| GitignoreFilter { repo });
+impl IgnoreFileFilter {
+    /// Apply the `.blobignore` files (gitignore syntax) found under `root`.
+    pub fn blobignore(root: PathBuf) -> IgnoreFileFilter {
+        let root = root.canonicalize().unwrap_or(root);
+
+        IgnoreFileFilter {
+            base: root,
+            file_name: ".blobignore",
+            excludes: vec![],
+            matchers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create a new filter for the files under `root`.
+    pub fn new(root: PathBuf) -> IgnoreFileFilter {
+        let root = root.canonicalize().unwrap_or(root);
+
+        let repository_root = root
+            .ancestors()
+            .find(|ancestor| ancestor.join(".git").exists())
+            .map(Path::to_path_buf);
+
+        let base = repository_root.clone().unwrap_or(root);
+
+        let excludes = [
+            repository_root.map(|repository_root| repository_root.join(".git/info/exclude")),
+            global_excludes_file(&base),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|ignore_file| IgnoreFileFilter::load(&base, &ignore_file))
+        .collect();
+
+        IgnoreFileFilter {
+            base,
+            file_name: ".gitignore",
+            excludes,
+            matchers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn load(dir: &Path, ignore_file: &Path) -> Option<Gitignore> {
+        if !ignore_file.is_file() {
+            return None;
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+
+        // A broken line only disables that pattern, the rest of the file still applies.
+        builder.add(ignore_file);
+
+        builder.build().ok()
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut matchers = self.matchers.lock().unwrap();
+
+        let dirs = path
+            .ancestors()
+            .skip(1)
+            .take_while(|ancestor| ancestor.starts_with(&self.base))
+            .collect::<Vec<_>>();
+
+        // The closest ignore file with a matching pattern decides, like git does.
+        for dir in dirs {
+            let matcher = matchers
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| IgnoreFileFilter::load(dir, &dir.join(self.file_name)));
+
+            if let Some(matcher) = matcher {
+                let matched = matcher.matched(path, is_dir);
 
-        match result {
-            Err(err) => {
-                if err.code() == git2::ErrorCode::NotFound {
-                    None
-                } else {
-                    Some(Err(From::from(err)))
+                if !matched.is_none() {
+                    return matched.is_ignore();
                 }
             }
-            Ok(repo) => Some(Ok(repo)),
         }
+
+        self.excludes
+            .iter()
+            .map(|exclude| exclude.matched(path, is_dir))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore())
+    }
+}
+
+/// The file set by `core.excludesFile`, read from the repository config when there
+/// is one, or git's default `$XDG_CONFIG_HOME/git/ignore`.
+fn global_excludes_file(base: &Path) -> Option<PathBuf> {
+    let config = match Repository::open(base) {
+        Ok(repository) => repository.config().ok(),
+        Err(_) => Config::open_default().ok(),
+    };
+
+    config
+        .and_then(|config| config.get_path("core.excludesFile").ok())
+        .or_else(|| {
+            let config_home = env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+            Some(config_home.join("git/ignore"))
+        })
+}
+
+/// Resolve the folders of `path` but not its last component, so a symbolic link is
+/// matched where it is rather than where it points.
+fn canonical_location(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .map(|parent| parent.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+    }
+}
+
+impl FileFilter for IgnoreFileFilter {
+    fn filter(&self, path: &Path) -> Result {
+        if path.file_name().is_some_and(|name| name == ".git") {
+            return Ok(false);
+        }
+
+        let path = canonical_location(path);
+
+        if !path.starts_with(&self.base) {
+            return Ok(true);
+        }
+
+        Ok(!self.is_ignored(&path, path.is_dir()))
+    }
+}
+
+/// Include and exclude globs matched against the path relative to `root`.
+///
+/// Excludes apply to folders too, includes only to files so that every folder can
+/// still be walked. Without includes every file passes.
+pub struct GlobFilter {
+    root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl GlobFilter {
+    pub fn new(
+        root: PathBuf,
+        include: &[String],
+        exclude: &[String],
+    ) -> result::Result<GlobFilter, Box<dyn Error>> {
+        let include = match include.is_empty() {
+            true => None,
+            false => Some(build_glob_set(include)?),
+        };
+
+        Ok(GlobFilter {
+            root: root.canonicalize().unwrap_or(root),
+            include,
+            exclude: build_glob_set(exclude)?,
+        })
+    }
+}
+
+/// Globs without a `/` match at any depth, like in ignore files.
+pub fn build_glob_set(patterns: &[String]) -> result::Result<GlobSet, Box<dyn Error>> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+
+        let pattern = match pattern.contains('/') {
+            true => pattern.to_string(),
+            false => format!("**/{pattern}"),
+        };
+
+        builder.add(GlobBuilder::new(&pattern).literal_separator(true).build()?);
+    }
+
+    Ok(builder.build()?)
+}
+
+impl FileFilter for GlobFilter {
+    fn filter(&self, path: &Path) -> Result {
+        let path = canonical_location(path);
+        let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+
+        if self.exclude.is_match(relative) {
+            return Ok(false);
+        }
+
+        match &self.include {
+            Some(include) if !path.is_dir() => Ok(include.is_match(relative)),
+            _ => Ok(true),
+        }
+    }
+}
+
+/// Skip files bigger than `max_bytes`.
+pub struct SizeFilter {
+    pub max_bytes: u64,
+}
+
+impl FileFilter for SizeFilter {
+    fn filter(&self, path: &Path) -> Result {
+        // A broken link has no target to measure, the link itself is tiny.
+        let metadata = fs::metadata(path).or_else(|_| fs::symlink_metadata(path))?;
+
+        Ok(metadata.is_dir() || metadata.len() <= self.max_bytes)
     }
 }
 
-impl FileFilter for GitignoreFilter {
+/// Skip files and folders whose name starts with a dot.
+pub struct HiddenFilter;
+
+impl FileFilter for HiddenFilter {
+    fn filter(&self, path: &Path) -> Result {
+        Ok(!path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.')))
+    }
+}
+
+/// Skip files that look binary, i.e. with a NUL byte in their first 8000 bytes
+/// (the same heuristic git uses).
+pub struct BinaryFilter;
+
+impl FileFilter for BinaryFilter {
     fn filter(&self, path: &Path) -> Result {
-        let path = path.canonicalize();
-        self.repo
-            .status_should_ignore(&path.unwrap())
-            .map(|x| !x)
-            .map_err(From::from)
+        // Folders and broken links have nothing to read.
+        if !path.is_file() {
+            return Ok(true);
+        }
+
+        let mut head = Vec::with_capacity(8000);
+
+        // A file that can't be read is still listed, it only can't be told binary.
+        let read = fs::File::open(path).and_then(|file| file.take(8000).read_to_end(&mut head));
+
+        Ok(read.is_err() || !head.contains(&0))
     }
 }
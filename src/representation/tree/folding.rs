@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::iterator::Entry;
+
+/// Children of a folder left out of the tree, written as a single summary line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Folded {
+    pub files: usize,
+    pub dirs: usize,
+    /// Most common extensions of the folded files, e.g. `json`.
+    pub extensions: Vec<String>,
+}
+
+/// Extensions listed in a summary line, the rest are left out.
+const MAX_FOLDED_EXTENSIONS: usize = 3;
+
+/// Words of an instruction that name no part of a project, a folder that happens
+/// to share one of them isn't expanded for it.
+const STOP_WORDS: &[&str] = &[
+    "about", "add", "after", "all", "also", "and", "any", "are", "before", "but", "can", "change",
+    "create", "delete", "does", "each", "every", "file", "files", "fix", "folder", "folders",
+    "for", "from", "has", "have", "how", "into", "its", "make", "move", "new", "not", "now", "one",
+    "only", "other", "our", "out", "put", "remove", "rename", "should", "some", "than", "that",
+    "the", "their", "them", "then", "there", "these", "this", "use", "using", "was", "what",
+    "when", "where", "which", "while", "who", "why", "will", "with", "would", "you", "your",
+];
+
+/// First character of every summary line, so parsers can tell it isn't a path.
+pub const FOLDED_MARKER: char = '…';
+
+impl Folded {
+    pub fn new(entries: &[Entry]) -> Self {
+        let mut folded = Folded::default();
+        let mut extensions: HashMap<String, usize> = HashMap::new();
+
+        for entry in entries {
+            if entry.is_dir() {
+                folded.dirs += 1;
+                continue;
+            }
+
+            folded.files += 1;
+
+            if let Some(extension) = entry.path().extension() {
+                *extensions
+                    .entry(extension.to_string_lossy().to_string())
+                    .or_default() += 1;
+            }
+        }
+
+        let mut extensions = extensions.into_iter().collect::<Vec<_>>();
+        extensions.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+
+        folded.extensions = extensions
+            .into_iter()
+            .take(MAX_FOLDED_EXTENSIONS)
+            .map(|(extension, _)| extension)
+            .collect();
+
+        folded
+    }
+}
+
+fn plural(count: usize, singular: &str, plural: &str) -> String {
+    match count {
+        1 => format!("1 more {singular}"),
+        count => format!("{count} more {plural}"),
+    }
+}
+
+impl fmt::Display for Folded {
+    /// `… 214 more files (*.json)`, `… 3 more files and 2 more folders (*.rs, *.md)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+
+        if self.files > 0 {
+            parts.push(plural(self.files, "file", "files"));
+        }
+
+        if self.dirs > 0 {
+            parts.push(plural(self.dirs, "folder", "folders"));
+        }
+
+        write!(f, "{FOLDED_MARKER} {}", parts.join(" and "))?;
+
+        if !self.extensions.is_empty() {
+            let extensions = self
+                .extensions
+                .iter()
+                .map(|extension| format!("*.{extension}"))
+                .collect::<Vec<_>>();
+
+            write!(f, " ({})", extensions.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How much of the project the tree shows, so large repositories still fit in a prompt.
+#[derive(Debug, Clone, Default)]
+pub struct TreeLimits {
+    /// Deepest level listed, the entries of the root are at depth 1.
+    pub max_depth: Option<usize>,
+    /// Folders with more children than this are folded into a summary line.
+    pub fold_threshold: Option<usize>,
+    /// Relative paths that are always listed, with their parents and everything inside.
+    pub expand: Vec<String>,
+    /// Lowercase words, entries named after one of them are always listed.
+    pub keywords: Vec<String>,
+}
+
+impl TreeLimits {
+    /// Expand whatever the instruction mentions: words that look like paths
+    /// (`src/cli`, `main.rs`) and names of at least 3 characters, stop words aside.
+    pub fn focus_on(mut self, instruction: &str) -> Self {
+        for word in instruction.split(|c: char| c.is_whitespace() || "`'\",;:()".contains(c)) {
+            let word = word.trim_matches(|c: char| c == '.' || c == '/');
+
+            if word.len() < 3 {
+                continue;
+            }
+
+            if word.contains('/') || word.contains('.') {
+                self.expand.push(word.trim_start_matches("./").to_string());
+            }
+
+            let keyword = word.to_lowercase();
+
+            if !STOP_WORDS.contains(&keyword.as_str()) {
+                self.keywords.push(keyword);
+            }
+        }
+
+        self
+    }
+
+    /// `relative` is the path of the entry from the root of the tree.
+    pub fn is_relevant(&self, relative: &str) -> bool {
+        let leads_to_or_inside = self.expand.iter().any(|expand| {
+            relative == expand
+                || expand.starts_with(&format!("{relative}/"))
+                || relative.starts_with(&format!("{expand}/"))
+        });
+
+        if leads_to_or_inside {
+            return true;
+        }
+
+        let name = relative
+            .rsplit('/')
+            .next()
+            .unwrap_or(relative)
+            .to_lowercase();
+        let stem = name.split('.').next().unwrap_or(&name);
+
+        self.keywords
+            .iter()
+            .any(|keyword| *keyword == name || (stem.len() >= 3 && keyword == stem))
+    }
+
+    /// Split the entries of a folder, at `depth`, between the listed ones and a
+    /// summary of the others.
+    pub fn fold(
+        &self,
+        entries: Vec<Entry>,
+        depth: usize,
+        relative: impl Fn(&Entry) -> String,
+    ) -> (Vec<Entry>, Option<Folded>) {
+        let too_deep = self.max_depth.is_some_and(|max_depth| depth > max_depth);
+        let too_many = self
+            .fold_threshold
+            .is_some_and(|threshold| entries.len() > threshold);
+
+        if !too_deep && !too_many {
+            return (entries, None);
+        }
+
+        let (mut listed, folded): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| self.is_relevant(&relative(entry)));
+
+        // A summary line is no shorter than the single entry it would replace, but
+        // past the maximum depth a listed folder would be walked further down.
+        if folded.is_empty() || (folded.len() < 2 && !too_deep) {
+            listed.extend(folded);
+            return (listed, None);
+        }
+
+        (listed, Some(Folded::new(&folded)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::TreeLimits;
+    use crate::representation::tree::formats::TreeFormat;
+    use crate::representation::tree::iterator::{TreeIter, WalkError};
+    use crate::representation::tree::order::TreeOrder;
+
+    /// A throwaway folder holding `files`, removed when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, files: &[&str]) -> Self {
+            let root = std::env::temp_dir().join(format!("blob-{name}-{}", std::process::id()));
+            fs::remove_dir_all(&root).ok();
+
+            for file in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, "").unwrap();
+            }
+
+            Fixture(root)
+        }
+
+        fn paths(&self, limits: TreeLimits) -> Vec<String> {
+            let mut tree = TreeIter::new(
+                self.0.clone(),
+                |_: &Path| -> Result<bool, WalkError> { Ok(true) },
+                TreeOrder::default(),
+            )
+            .unwrap()
+            .with_limits(limits)
+            .unwrap();
+
+            TreeFormat::Paths
+                .processor(None)
+                .construct(&mut tree)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn single_child_chains_stop_at_the_maximum_depth() {
+        let fixture = Fixture::new("depth", &["a/b/c/d/e/deep.txt", "x/y/z.txt", "top.txt"]);
+
+        let paths = fixture.paths(TreeLimits {
+            max_depth: Some(1),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            paths,
+            [
+                "a/",
+                "a/… 1 more folder",
+                "x/",
+                "x/… 1 more folder",
+                "top.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn large_folders_are_folded_unless_a_single_entry_is_left() {
+        let fixture = Fixture::new("threshold", &["a/1.rs", "a/2.rs", "a/3.rs"]);
+
+        let paths = fixture.paths(TreeLimits {
+            fold_threshold: Some(2),
+            ..Default::default()
+        });
+
+        assert_eq!(paths, ["a/", "a/… 3 more files (*.rs)"]);
+
+        let fixture = Fixture::new("threshold-one", &["a/1.rs", "a/2.rs"]);
+
+        let paths = fixture.paths(TreeLimits {
+            fold_threshold: Some(1),
+            expand: vec!["a/1.rs".to_string()],
+            ..Default::default()
+        });
+
+        assert_eq!(paths, ["a/", "a/1.rs", "a/2.rs"]);
+    }
+
+    #[test]
+    fn expanded_paths_are_listed_past_the_maximum_depth() {
+        let fixture = Fixture::new("expand", &["a/b/c.txt", "a/b/d.txt"]);
+
+        let paths = fixture.paths(TreeLimits {
+            max_depth: Some(1),
+            expand: vec!["a/b/c.txt".to_string()],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            paths,
+            ["a/", "a/b/", "a/b/c.txt", "a/b/… 1 more file (*.txt)"]
+        );
+    }
+}
@@ -0,0 +1,724 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use serde_derive::{Deserialize, Serialize};
+
+use super::annotations::{leaf_name, note, strip_annotation, suffix, Annotations};
+use super::folding::{Folded, FOLDED_MARKER};
+use super::iterator::{Entry, TreeProcessor};
+use super::representation::{file_name_from_path, TreeRepresentation};
+
+/// How the project structure is written for the model and `blob tree`.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TreeFormat {
+    /// Box-drawing tree, like `tree`.
+    #[default]
+    Tree,
+    /// Same tree drawn with `|`, `-` and `` ` `` only.
+    Ascii,
+    /// One relative path per line, folders end with `/`.
+    Paths,
+    /// Names indented by two spaces per level, folders end with `/`.
+    Outline,
+    /// Nested JSON array, a folder is an object `{"name/": [children]}`. Links and
+    /// submodules carry what they are in a `note`, e.g. `{"name": "docs", "note": "symlink to doc"}`.
+    Json,
+    /// Same nesting as `json`, written as YAML.
+    Yaml,
+}
+
+impl fmt::Display for TreeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+impl TreeFormat {
+    /// Annotations only apply to the line based formats, JSON and YAML keep plain names.
+    pub fn processor(&self, annotations: Option<Arc<Annotations>>) -> Box<dyn TreeProcessor> {
+        match self {
+            TreeFormat::Tree => Box::new(TreeRepresentation::new().annotated(annotations)),
+            TreeFormat::Ascii => Box::new(TreeRepresentation::ascii().annotated(annotations)),
+            TreeFormat::Paths => Box::new(PathListRepresentation {
+                annotations,
+                ..Default::default()
+            }),
+            TreeFormat::Outline => Box::new(OutlineRepresentation {
+                annotations,
+                ..Default::default()
+            }),
+            TreeFormat::Json => Box::new(StructuredRepresentation::new(StructuredSyntax::Json)),
+            TreeFormat::Yaml => Box::new(StructuredRepresentation::new(StructuredSyntax::Yaml)),
+        }
+    }
+
+    /// Read back a structure written in this format, e.g. one proposed by the model.
+    ///
+    /// Returns the relative paths in order, folders end with `/`. The model doesn't
+    /// always mark folders, so in the tree formats any entry with children is one.
+    pub fn parse(&self, text: &str) -> Result<Vec<String>> {
+        let paths = match self {
+            TreeFormat::Tree | TreeFormat::Ascii => {
+                paths_from_indented(text.lines().filter_map(tree_line))
+            }
+            TreeFormat::Outline => paths_from_indented(text.lines().filter_map(outline_line)),
+            TreeFormat::Paths => text
+                .lines()
+                .map(|line| strip_annotation(line).trim().trim_start_matches("./"))
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+            TreeFormat::Json => {
+                let value = serde_json::from_str::<serde_json::Value>(strip_fences(text))?;
+                let mut paths = vec![];
+                json_paths(&value, "", &mut paths)?;
+                paths
+            }
+            TreeFormat::Yaml => {
+                let value = serde_yaml::from_str::<serde_yaml::Value>(strip_fences(text))?;
+                let mut paths = vec![];
+                yaml_paths(&value, "", &mut paths)?;
+                paths
+            }
+        };
+
+        let paths = paths
+            .into_iter()
+            .filter(|path| !is_summary(path))
+            .collect::<Vec<_>>();
+
+        if paths.is_empty() {
+            return Err(anyhow!("No paths found in the {self} structure"));
+        }
+
+        Ok(paths)
+    }
+
+    /// Parse a structure edited by hand, also rejecting entries nested more than one
+    /// level below the previous one and paths listed twice.
+    pub fn validate(&self, text: &str) -> Result<Vec<String>> {
+        let indented: Option<LineParser> = match self {
+            TreeFormat::Tree | TreeFormat::Ascii => Some(tree_line),
+            TreeFormat::Outline => Some(outline_line),
+            _ => None,
+        };
+
+        if let Some(indented) = indented {
+            let mut previous_depth = None;
+
+            for (number, line) in text.lines().enumerate() {
+                let Some((depth, name)) = indented(line) else {
+                    continue;
+                };
+
+                if is_summary(&name) {
+                    continue;
+                }
+
+                let max_depth = previous_depth.map(|previous| previous + 1).unwrap_or(0);
+
+                if depth > max_depth {
+                    return Err(anyhow!(
+                        "Line {} ('{}') is nested deeper than the entry above it",
+                        number + 1,
+                        name
+                    ));
+                }
+
+                previous_depth = Some(depth);
+            }
+        }
+
+        let paths = self.parse(text)?;
+
+        let mut seen = HashSet::new();
+
+        for path in paths.iter() {
+            if path.trim_end_matches('/').split('/').any(str::is_empty) {
+                return Err(anyhow!("'{path}' has an empty name"));
+            }
+
+            if !seen.insert(path.trim_end_matches('/')) {
+                return Err(anyhow!("'{path}' is listed twice"));
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// [`TreeFormat::validate`] an edit of the `current` structure, entries can't be
+    /// nested under a name that is a file now or in the edit.
+    pub fn validate_edit(&self, current: &str, text: &str) -> Result<Vec<String>> {
+        let paths = self.validate(text)?;
+
+        let files = self
+            .parse(current)
+            .unwrap_or_default()
+            .into_iter()
+            .chain(paths.iter().cloned())
+            .filter(|path| !path.ends_with('/'))
+            .collect::<HashSet<_>>();
+
+        for path in paths.iter() {
+            let parents = path
+                .trim_end_matches('/')
+                .match_indices('/')
+                .map(|(index, _)| &path[..index]);
+
+            for parent in parents {
+                if files.contains(parent) {
+                    return Err(anyhow!("'{parent}' is a file, it can't contain '{path}'"));
+                }
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// Extension of a file holding a structure in this format, for the editor.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TreeFormat::Json => "json",
+            TreeFormat::Yaml => "yaml",
+            _ => "txt",
+        }
+    }
+}
+
+/// Names of the open folders, the root is never pushed.
+#[derive(Default)]
+struct DirStack {
+    names: Vec<String>,
+}
+
+impl DirStack {
+    fn open(&mut self, entry: &Entry) -> String {
+        let name = file_name_from_path(entry.path()).to_string();
+        let path = self.path_of(&name);
+
+        self.names.push(name);
+
+        path
+    }
+
+    /// The iterator also closes the root folder, which was never opened.
+    fn close(&mut self) {
+        self.names.pop();
+    }
+
+    fn path_of(&self, name: &str) -> String {
+        match self.names.is_empty() {
+            true => name.to_string(),
+            false => format!("{}/{}", self.names.join("/"), name),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PathListRepresentation {
+    dirs: DirStack,
+    annotations: Option<Arc<Annotations>>,
+}
+
+impl TreeProcessor for PathListRepresentation {
+    fn construct_dir(&mut self, entry: &Entry) -> String {
+        format!(
+            "{}/{}\n",
+            self.dirs.open(entry),
+            suffix(self.annotations.as_deref(), entry)
+        )
+    }
+
+    fn close_dir(&mut self) {
+        self.dirs.close();
+    }
+
+    fn construct_file(&mut self, entry: &Entry) -> String {
+        format!(
+            "{}{}\n",
+            self.dirs.path_of(&leaf_name(entry)),
+            suffix(self.annotations.as_deref(), entry)
+        )
+    }
+
+    fn construct_folded(&mut self, folded: &Folded) -> String {
+        format!("{}\n", self.dirs.path_of(&folded.to_string()))
+    }
+}
+
+#[derive(Default)]
+pub struct OutlineRepresentation {
+    depth: usize,
+    annotations: Option<Arc<Annotations>>,
+}
+
+impl TreeProcessor for OutlineRepresentation {
+    fn construct_dir(&mut self, entry: &Entry) -> String {
+        let line = format!(
+            "{}{}/{}\n",
+            "  ".repeat(self.depth),
+            file_name_from_path(entry.path()),
+            suffix(self.annotations.as_deref(), entry)
+        );
+
+        self.depth += 1;
+
+        line
+    }
+
+    fn close_dir(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    fn construct_file(&mut self, entry: &Entry) -> String {
+        format!(
+            "{}{}{}\n",
+            "  ".repeat(self.depth),
+            leaf_name(entry),
+            suffix(self.annotations.as_deref(), entry)
+        )
+    }
+
+    fn construct_folded(&mut self, folded: &Folded) -> String {
+        format!("{}{}\n", "  ".repeat(self.depth), folded)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum StructuredSyntax {
+    Json,
+    Yaml,
+}
+
+/// Names stay plain, what a link or submodule is goes in a separate `note` field.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum StructureNode {
+    File(String),
+    NotedFile {
+        name: String,
+        note: String,
+    },
+    Dir(BTreeMap<String, Vec<StructureNode>>),
+    NotedDir {
+        #[serde(flatten)]
+        dir: BTreeMap<String, Vec<StructureNode>>,
+        note: String,
+    },
+}
+
+/// An open folder, its note and its children so far.
+type OpenDir = (String, Option<String>, Vec<StructureNode>);
+
+/// Builds the whole structure in memory and serializes it in `finish`.
+pub struct StructuredRepresentation {
+    syntax: StructuredSyntax,
+    /// Open folders, the first one is the root.
+    stack: Vec<OpenDir>,
+}
+
+impl StructuredRepresentation {
+    pub fn new(syntax: StructuredSyntax) -> Self {
+        StructuredRepresentation {
+            syntax,
+            stack: vec![(String::new(), None, vec![])],
+        }
+    }
+}
+
+impl TreeProcessor for StructuredRepresentation {
+    fn construct_dir(&mut self, entry: &Entry) -> String {
+        let name = file_name_from_path(entry.path()).to_string();
+
+        self.stack
+            .push((format!("{name}/"), note(None, entry), vec![]));
+
+        String::new()
+    }
+
+    fn close_dir(&mut self) {
+        if self.stack.len() > 1 {
+            let (name, note, children) = self.stack.pop().unwrap();
+            let dir = BTreeMap::from([(name, children)]);
+
+            let node = match note {
+                Some(note) => StructureNode::NotedDir { dir, note },
+                None => StructureNode::Dir(dir),
+            };
+
+            self.stack.last_mut().unwrap().2.push(node);
+        }
+    }
+
+    fn construct_file(&mut self, entry: &Entry) -> String {
+        let name = leaf_name(entry);
+
+        let node = match note(None, entry) {
+            Some(note) => StructureNode::NotedFile { name, note },
+            None => StructureNode::File(name),
+        };
+
+        self.stack.last_mut().unwrap().2.push(node);
+
+        String::new()
+    }
+
+    fn construct_folded(&mut self, folded: &Folded) -> String {
+        self.stack
+            .last_mut()
+            .unwrap()
+            .2
+            .push(StructureNode::File(folded.to_string()));
+
+        String::new()
+    }
+
+    fn finish(&mut self) -> String {
+        while self.stack.len() > 1 {
+            self.close_dir();
+        }
+
+        let (_, _, root) = std::mem::take(&mut self.stack[0]);
+
+        match self.syntax {
+            StructuredSyntax::Json => serde_json::to_string_pretty(&root).unwrap() + "\n",
+            StructuredSyntax::Yaml => serde_yaml::to_string(&root).unwrap(),
+        }
+    }
+}
+
+/// Folded entries (`… 12 more files`) and the `3 directories, 12 files` footer.
+fn is_summary(path: &str) -> bool {
+    let name = path
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(path);
+
+    let footer = !path.contains('/')
+        && name.starts_with(|c: char| c.is_ascii_digit())
+        && (name.contains(" director") || name.ends_with(" folded"));
+
+    name.starts_with(FOLDED_MARKER) || footer
+}
+
+/// Depth and name of a line of an indented format, `None` for blank lines.
+type LineParser = fn(&str) -> Option<(usize, String)>;
+
+/// Depth and name of a `tree` line, whatever characters draw the branches.
+fn tree_line(line: &str) -> Option<(usize, String)> {
+    let name = line.trim_start_matches(|c: char| "│├└─|`- \u{a0}".contains(c));
+
+    if name.trim().is_empty() {
+        return None;
+    }
+
+    let prefix = line[..line.len() - name.len()].chars().count();
+
+    Some((
+        prefix.saturating_sub(4) / 4,
+        strip_annotation(name).trim_end().to_string(),
+    ))
+}
+
+fn outline_line(line: &str) -> Option<(usize, String)> {
+    let name = line.trim_start();
+
+    if name.is_empty() {
+        return None;
+    }
+
+    let indent = line.len() - name.len();
+
+    Some((indent / 2, strip_annotation(name).trim_end().to_string()))
+}
+
+/// Turn `(depth, name)` lines into relative paths, folders end with `/`.
+fn paths_from_indented<I: Iterator<Item = (usize, String)>>(lines: I) -> Vec<String> {
+    let lines = lines.collect::<Vec<_>>();
+
+    let mut stack: Vec<String> = vec![];
+    let mut paths = vec![];
+
+    for (index, (depth, name)) in lines.iter().enumerate() {
+        stack.truncate(*depth);
+
+        let name = name.trim_end_matches('/');
+        let has_children = lines
+            .get(index + 1)
+            .is_some_and(|(next_depth, _)| next_depth > depth);
+
+        let mut path = stack
+            .iter()
+            .map(|dir| format!("{dir}/"))
+            .collect::<String>();
+        path.push_str(name);
+
+        if has_children || lines[index].1.ends_with('/') {
+            path.push('/');
+            stack.push(name.to_string());
+        }
+
+        paths.push(path);
+    }
+
+    paths
+}
+
+/// Models tend to wrap structured answers in a markdown code block.
+fn strip_fences(text: &str) -> &str {
+    let text = text.trim();
+
+    match text.strip_prefix("```") {
+        Some(rest) => {
+            let rest = rest.split_once('\n').map(|(_, body)| body).unwrap_or("");
+            rest.trim_end().trim_end_matches("```")
+        }
+        None => text,
+    }
+}
+
+fn json_paths(value: &serde_json::Value, parent: &str, paths: &mut Vec<String>) -> Result<()> {
+    match value {
+        serde_json::Value::Array(nodes) => {
+            for node in nodes {
+                json_paths(node, parent, paths)?;
+            }
+        }
+        serde_json::Value::String(name) => paths.push(format!("{parent}{name}")),
+        serde_json::Value::Object(node) => {
+            // A link or submodule with its note.
+            if let Some(serde_json::Value::String(name)) = node.get("name") {
+                paths.push(format!("{parent}{name}"));
+                return Ok(());
+            }
+
+            for (name, children) in node {
+                if name == "note" && children.is_string() {
+                    continue;
+                }
+
+                let dir = format!("{parent}{}/", name.trim_end_matches('/'));
+                paths.push(dir.clone());
+                json_paths(children, &dir, paths)?;
+            }
+        }
+        serde_json::Value::Null => {}
+        other => return Err(anyhow!("Unexpected {other} in the JSON structure")),
+    }
+
+    Ok(())
+}
+
+fn yaml_paths(value: &serde_yaml::Value, parent: &str, paths: &mut Vec<String>) -> Result<()> {
+    match value {
+        serde_yaml::Value::Sequence(nodes) => {
+            for node in nodes {
+                yaml_paths(node, parent, paths)?;
+            }
+        }
+        serde_yaml::Value::String(name) => paths.push(format!("{parent}{name}")),
+        serde_yaml::Value::Mapping(node) => {
+            // A link or submodule with its note.
+            if let Some(serde_yaml::Value::String(name)) = node.get("name") {
+                paths.push(format!("{parent}{name}"));
+                return Ok(());
+            }
+
+            for (name, children) in node {
+                let name = name
+                    .as_str()
+                    .ok_or_else(|| anyhow!("Folder names must be strings in the YAML structure"))?;
+
+                if name == "note" && children.is_string() {
+                    continue;
+                }
+
+                let dir = format!("{parent}{}/", name.trim_end_matches('/'));
+                paths.push(dir.clone());
+                yaml_paths(children, &dir, paths)?;
+            }
+        }
+        serde_yaml::Value::Null => {}
+        other => return Err(anyhow!("Unexpected {other:?} in the YAML structure")),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::TreeFormat;
+    use crate::representation::tree::iterator::{TreeIter, WalkError};
+    use crate::representation::tree::order::TreeOrder;
+
+    const ALL: [TreeFormat; 6] = [
+        TreeFormat::Tree,
+        TreeFormat::Ascii,
+        TreeFormat::Paths,
+        TreeFormat::Outline,
+        TreeFormat::Json,
+        TreeFormat::Yaml,
+    ];
+
+    /// A throwaway folder holding `files`, removed when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, files: &[&str]) -> Self {
+            let root = std::env::temp_dir().join(format!("blob-{name}-{}", std::process::id()));
+            fs::remove_dir_all(&root).ok();
+
+            for file in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, "").unwrap();
+            }
+
+            Fixture(root)
+        }
+
+        fn render(&self, format: TreeFormat) -> String {
+            let mut tree = TreeIter::new(
+                self.0.clone(),
+                |_: &Path| -> Result<bool, WalkError> { Ok(true) },
+                TreeOrder::default(),
+            )
+            .unwrap();
+
+            format.processor(None).construct(&mut tree).unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn every_format_reads_back_the_paths_it_wrote() {
+        let fixture = Fixture::new(
+            "formats",
+            &[
+                "README.md",
+                "src/main.rs",
+                "src/util/mod.rs",
+                "docs/guide.md",
+            ],
+        );
+
+        let expected = [
+            "docs/",
+            "docs/guide.md",
+            "src/",
+            "src/util/",
+            "src/util/mod.rs",
+            "src/main.rs",
+            "README.md",
+        ];
+
+        for format in ALL {
+            let text = fixture.render(format);
+
+            assert_eq!(format.parse(&text).unwrap(), expected, "{format}:\n{text}");
+            assert_eq!(format.validate(&text).unwrap(), expected, "{format}");
+        }
+    }
+
+    #[test]
+    fn structured_formats_keep_names_that_look_annotated() {
+        let fixture = Fixture::new("formats-names", &["notes  # draft.txt"]);
+
+        for format in [TreeFormat::Json, TreeFormat::Yaml] {
+            let text = fixture.render(format);
+
+            assert_eq!(
+                format.parse(&text).unwrap(),
+                ["notes  # draft.txt"],
+                "{format}"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn structured_formats_put_links_in_a_note() {
+        let fixture = Fixture::new("formats-links", &["doc/guide.md"]);
+        std::os::unix::fs::symlink("doc", fixture.0.join("docs")).unwrap();
+
+        let json = fixture.render(TreeFormat::Json);
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+
+        assert_eq!(
+            value[1],
+            serde_json::json!({"name": "docs", "note": "symlink to doc"})
+        );
+
+        for format in [TreeFormat::Json, TreeFormat::Yaml] {
+            let text = fixture.render(format);
+
+            assert_eq!(
+                format.parse(&text).unwrap(),
+                ["doc/", "doc/guide.md", "docs"],
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn model_answers_are_read_through_code_fences() {
+        let answer = "```json\n[{\"src/\": [\"lib.rs\"]}, \"Cargo.toml\"]\n```";
+
+        assert_eq!(
+            TreeFormat::Json.parse(answer).unwrap(),
+            ["src/", "src/lib.rs", "Cargo.toml"]
+        );
+    }
+
+    #[test]
+    fn tree_entries_with_children_are_folders() {
+        let text = "├── src\n│   └── lib.rs\n└── Cargo.toml  # 1 KB\n\n1 directory, 2 files\n";
+
+        assert_eq!(
+            TreeFormat::Tree.parse(text).unwrap(),
+            ["src/", "src/lib.rs", "Cargo.toml"]
+        );
+    }
+
+    #[test]
+    fn hand_edits_are_validated() {
+        let error = |format: TreeFormat, text: &str| format.validate(text).unwrap_err().to_string();
+
+        assert_eq!(
+            error(TreeFormat::Outline, "src/\n      lib.rs\n"),
+            "Line 2 ('lib.rs') is nested deeper than the entry above it"
+        );
+        assert_eq!(
+            error(TreeFormat::Paths, "src/lib.rs\nsrc/lib.rs\n"),
+            "'src/lib.rs' is listed twice"
+        );
+        assert_eq!(
+            error(TreeFormat::Paths, "src//lib.rs\n"),
+            "'src//lib.rs' has an empty name"
+        );
+        assert_eq!(
+            error(TreeFormat::Paths, "\n\n"),
+            "No paths found in the paths structure"
+        );
+
+        let edit = TreeFormat::Paths.validate_edit("src/\nsrc/lib.rs\n", "src/lib.rs/mod.rs\n");
+
+        assert_eq!(
+            edit.unwrap_err().to_string(),
+            "'src/lib.rs' is a file, it can't contain 'src/lib.rs/mod.rs'"
+        );
+    }
+}
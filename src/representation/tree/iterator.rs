@@ -1,23 +1,73 @@
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
 use std::fs;
 use std::iter::Peekable;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::{stream, Stream};
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinHandle};
 
 use crate::representation::tree::filters::FileFilter;
+use crate::representation::tree::folding::{Folded, TreeLimits};
+use crate::representation::tree::links::{is_special, submodule_paths, SymlinkPolicy};
+use crate::representation::tree::order::TreeOrder;
+
+/// Errors of a walk can cross threads, so the async walk can report them.
+pub type WalkError = Box<dyn Error + Send + Sync>;
+
+/// Events of [`TreeIter::into_stream`], in the same order as the iterator.
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<Event, WalkError>> + Send>>;
 
 #[derive(Debug)]
 pub enum Event {
     File(Entry),
     OpenDir(Entry),
+    /// Summary of the entries left out of the current folder, always its last child.
+    Folded(Folded),
     CloseDir,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    /// A git submodule, listed as a leaf.
+    Submodule,
+    /// A symbolic link listed as a leaf, see [`Link`] for why.
+    Symlink,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// Listed as is, see [`SymlinkPolicy::Show`].
+    Shown,
+    /// Walked like the file or folder it points to.
+    Followed,
+    /// Points to nothing.
+    Broken,
+    /// Points to one of its parents, following it would never end.
+    Cycle,
+    /// Points out of the root of the walk, its content isn't part of the tree.
+    Outside,
+}
+
+/// Where a symbolic link points, as written in the link.
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub target: PathBuf,
+    pub state: LinkState,
+}
+
 pub struct Entry {
     path: PathBuf,
     has_next_sibling: bool,
     metadata: fs::Metadata,
+    kind: EntryKind,
+    link: Option<Link>,
 }
 
 impl Entry {
@@ -28,6 +78,25 @@ impl Entry {
     pub fn has_next_sibling(&self) -> bool {
         self.has_next_sibling
     }
+
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    /// Folders and submodules, whether they are walked or not.
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, EntryKind::Dir | EntryKind::Submodule)
+    }
+
+    /// Set when the entry is a symbolic link, followed or not.
+    pub fn link(&self) -> Option<&Link> {
+        self.link.as_ref()
+    }
+
+    /// Size in bytes, of the target for followed links.
+    pub fn size(&self) -> u64 {
+        self.metadata.len()
+    }
 }
 
 impl fmt::Debug for Entry {
@@ -36,91 +105,444 @@ impl fmt::Debug for Entry {
             .debug_struct("Entry")
             .field("path", &self.path)
             .field("has_next_sibling", &self.has_next_sibling)
-            .field("is_dir", &self.metadata.is_dir())
+            .field("kind", &self.kind)
+            .field("link", &self.link)
             .finish()
     }
 }
 
+/// Everything the walk needs to list a folder.
+struct WalkSettings {
+    root: PathBuf,
+    /// Followed links must resolve inside it.
+    canonical_root: PathBuf,
+    file_filter: Arc<dyn FileFilter>,
+    order: TreeOrder,
+    limits: TreeLimits,
+    symlinks: SymlinkPolicy,
+    /// Canonical paths of the submodules of the repository.
+    submodules: HashSet<PathBuf>,
+}
+
+impl WalkSettings {
+    /// Canonical paths of the folders from the root to `dir`, to tell when a followed
+    /// link leads back to one of them. Only needed when links are followed.
+    fn ancestors_of(&self, ancestors: &[PathBuf], dir: &Path) -> Vec<PathBuf> {
+        if self.symlinks != SymlinkPolicy::Follow {
+            return vec![];
+        }
+
+        let mut ancestors = ancestors.to_vec();
+        ancestors.extend(dir.canonicalize().ok());
+
+        ancestors
+    }
+
+    /// `None` for the entries left out whatever the filters say: sockets, FIFOs,
+    /// devices and, depending on the policy, symbolic links.
+    fn classify(
+        &self,
+        dir_entry: &fs::DirEntry,
+        canonical_dir: Option<&Path>,
+        ancestors: &[PathBuf],
+    ) -> Result<Option<Entry>, WalkError> {
+        let path = dir_entry.path();
+        let file_type = dir_entry.file_type()?;
+
+        if is_special(&file_type) {
+            return Ok(None);
+        }
+
+        if file_type.is_symlink() {
+            return self.classify_link(path, ancestors);
+        }
+
+        let is_submodule = file_type.is_dir()
+            && canonical_dir
+                .is_some_and(|dir| self.submodules.contains(&dir.join(dir_entry.file_name())));
+
+        let kind = match (file_type.is_dir(), is_submodule) {
+            (true, true) => EntryKind::Submodule,
+            (true, false) => EntryKind::Dir,
+            (false, _) => EntryKind::File,
+        };
+
+        Ok(Some(Entry {
+            path,
+            has_next_sibling: false,
+            metadata: dir_entry.metadata()?,
+            kind,
+            link: None,
+        }))
+    }
+
+    fn classify_link(
+        &self,
+        path: PathBuf,
+        ancestors: &[PathBuf],
+    ) -> Result<Option<Entry>, WalkError> {
+        let target = fs::read_link(&path)?;
+
+        let leaf = |state: LinkState| -> Result<Option<Entry>, WalkError> {
+            Ok(Some(Entry {
+                metadata: fs::symlink_metadata(&path)?,
+                path: path.clone(),
+                has_next_sibling: false,
+                kind: EntryKind::Symlink,
+                link: Some(Link {
+                    target: target.clone(),
+                    state,
+                }),
+            }))
+        };
+
+        if self.symlinks == SymlinkPolicy::Skip {
+            return Ok(None);
+        }
+
+        if self.symlinks == SymlinkPolicy::Show {
+            return leaf(LinkState::Shown);
+        }
+
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => return leaf(LinkState::Broken),
+        };
+
+        if is_special(&metadata.file_type()) {
+            return Ok(None);
+        }
+
+        let kind = match metadata.is_dir() {
+            true => EntryKind::Dir,
+            false => EntryKind::File,
+        };
+
+        let resolved = path.canonicalize()?;
+
+        if !resolved.starts_with(&self.canonical_root) {
+            return leaf(LinkState::Outside);
+        }
+
+        if kind == EntryKind::Dir && ancestors.contains(&resolved) {
+            return leaf(LinkState::Cycle);
+        }
+
+        Ok(Some(Entry {
+            path,
+            has_next_sibling: false,
+            metadata,
+            kind,
+            link: Some(Link {
+                target,
+                state: LinkState::Followed,
+            }),
+        }))
+    }
+}
+
+enum Listed {
+    Entry(Entry),
+    Folded(Folded),
+}
+
+/// Entries of a folder that pass the filter, read at once so they can be sorted
+/// and folded.
 struct FilteredDir {
-    file_filter: Rc<dyn FileFilter>,
-    dir: fs::ReadDir,
+    entries: std::vec::IntoIter<Result<Listed, WalkError>>,
 }
 
 impl FilteredDir {
-    pub fn new<P>(path: P, file_filter: Rc<dyn FileFilter>) -> Result<Self, Box<dyn Error>>
+    /// `depth` is the depth of the entries of the folder, 1 for the root, and
+    /// `ancestors` come from [`WalkSettings::ancestors_of`].
+    fn new<P>(
+        path: P,
+        settings: &WalkSettings,
+        depth: usize,
+        ancestors: &[PathBuf],
+    ) -> Result<Self, WalkError>
     where
         P: AsRef<Path>,
     {
-        fs::read_dir(&path)
-            .map(|dir| FilteredDir {
-                file_filter: file_filter,
-                dir: dir,
-            })
-            .map_err(|err| {
-                From::from(format!(
-                    "Failed to read dir '{}': {}",
-                    path.as_ref().display(),
-                    err
-                ))
-            })
+        let dir = fs::read_dir(&path)
+            .map_err(|err| format!("Failed to read dir '{}': {}", path.as_ref().display(), err))?;
+
+        // Submodules are recognized by their canonical path.
+        let canonical_dir = match settings.submodules.is_empty() {
+            true => None,
+            false => path.as_ref().canonicalize().ok(),
+        };
+
+        let mut entries = vec![];
+        let mut errors = vec![];
+
+        for result in dir {
+            let entry = match result
+                .map_err(WalkError::from)
+                .and_then(|entry| settings.classify(&entry, canonical_dir.as_deref(), ancestors))
+            {
+                Ok(Some(entry)) => entry,
+                Ok(None) => continue,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+
+            match settings.file_filter.filter(entry.path()) {
+                Ok(true) => entries.push(entry),
+                Ok(false) => {}
+                Err(err) => errors.push(err),
+            }
+        }
+
+        entries.sort_by(|a, b| settings.order.compare(a, b));
+
+        let (entries, folded) = settings.limits.fold(entries, depth, |entry| {
+            let path = entry.path();
+            let relative = path.strip_prefix(&settings.root).unwrap_or(path);
+
+            relative.to_string_lossy().replace('\\', "/")
+        });
+
+        // Errors come first, the walk stops at the first one anyway. The summary of
+        // the folded entries comes last.
+        let entries = errors
+            .into_iter()
+            .map(Err)
+            .chain(entries.into_iter().map(|entry| Ok(Listed::Entry(entry))))
+            .chain(folded.map(|folded| Ok(Listed::Folded(folded))))
+            .collect::<Vec<_>>();
+
+        Ok(FilteredDir {
+            entries: entries.into_iter(),
+        })
     }
 }
 
 impl Iterator for FilteredDir {
-    type Item = Result<fs::DirEntry, Box<dyn Error>>;
+    type Item = Result<Listed, WalkError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let result = match self.dir.next() {
-                Some(result) => result,
-                None => return None,
-            };
+        self.entries.next()
+    }
+}
 
-            let entry = match result {
-                Ok(entry) => entry,
-                Err(err) => return Some(Err(From::from(err))),
-            };
+/// Folders read at the same time by the async walk.
+const MAX_CONCURRENT_LISTINGS: usize = 8;
 
-            let should_yield = match self.file_filter.filter(entry.path().as_path()) {
-                Ok(should_yield) => should_yield,
-                Err(err) => return Some(Err(From::from(err))),
-            };
+/// Entry of a folder read by the async walk. The content of a child folder is
+/// read in the background as soon as its parent is listed.
+enum Node {
+    File(Entry),
+    Dir(Entry, Listing),
+    Folded(Folded),
+}
 
-            if should_yield {
-                return Some(Ok(entry));
-            }
-        }
+/// Content of a folder read in the background. Dropping it cancels the read and,
+/// through its nodes, the reads of its child folders, so a walk that stops early
+/// doesn't keep reading the tree.
+struct Listing(JoinHandle<Result<Vec<Result<Node, WalkError>>, WalkError>>);
+
+impl Listing {
+    async fn join(&mut self) -> Result<std::vec::IntoIter<Result<Node, WalkError>>, WalkError> {
+        Ok((&mut self.0).await??.into_iter())
     }
 }
 
+impl Drop for Listing {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Settings of the async walk and the permits bounding how many folders are read
+/// at once, shared by every listing.
+#[derive(Clone)]
+struct Walker {
+    settings: Arc<WalkSettings>,
+    permits: Arc<Semaphore>,
+}
+
+impl Walker {
+    /// Read a folder on the blocking pool once a permit is free, then its child
+    /// folders the same way.
+    fn spawn_listing(&self, path: PathBuf, depth: usize, ancestors: Vec<PathBuf>) -> Listing {
+        let walker = self.clone();
+
+        Listing(task::spawn(async move {
+            let permit = walker.permits.clone().acquire_owned().await?;
+
+            let settings = walker.settings.clone();
+            let dir_ancestors = ancestors.clone();
+
+            let listed = task::spawn_blocking(move || {
+                let _permit = permit;
+
+                FilteredDir::new(&path, &settings, depth, &dir_ancestors)
+                    .map(|dir| dir.collect::<Vec<_>>())
+            })
+            .await??;
+
+            Ok(walker.nodes(listed, depth, &ancestors))
+        }))
+    }
+
+    /// Nodes of the entries listed in a folder at `depth`, every child folder starts
+    /// being read.
+    fn nodes(
+        &self,
+        listed: Vec<Result<Listed, WalkError>>,
+        depth: usize,
+        ancestors: &[PathBuf],
+    ) -> Vec<Result<Node, WalkError>> {
+        let last = listed.len().saturating_sub(1);
+
+        listed
+            .into_iter()
+            .enumerate()
+            .map(|(index, listed)| {
+                let mut entry = match listed? {
+                    Listed::Entry(entry) => entry,
+                    Listed::Folded(folded) => return Ok(Node::Folded(folded)),
+                };
+
+                // Errors come first, every entry after the first one is a sibling.
+                entry.has_next_sibling = index < last;
+
+                match entry.kind {
+                    EntryKind::Dir => {
+                        let listing = self.spawn_listing(
+                            entry.path.clone(),
+                            depth + 1,
+                            self.settings.ancestors_of(ancestors, &entry.path),
+                        );
+
+                        Ok(Node::Dir(entry, listing))
+                    }
+                    _ => Ok(Node::File(entry)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Folders already read by the iterator, with the depth of their entries and their
+/// ancestors, from the root down.
+type OpenDirs = Vec<(Vec<Result<Listed, WalkError>>, usize, Vec<PathBuf>)>;
+
+/// State of the async walk. The folders the iterator already read are turned into
+/// nodes on the first poll, inside the runtime.
+struct StreamState {
+    open: Option<(Walker, OpenDirs)>,
+    dir_stack: Vec<std::vec::IntoIter<Result<Node, WalkError>>>,
+}
+
 pub struct TreeIter {
     dir_stack: Vec<Peekable<FilteredDir>>,
-    file_filter: Rc<dyn FileFilter>,
+    /// Canonical paths of the open folders, see [`WalkSettings::ancestors_of`].
+    ancestors: Vec<Vec<PathBuf>>,
+    settings: WalkSettings,
 }
 
 impl TreeIter {
-    /// Create a new iterator with `path` as root.
-    pub fn new<F>(path: PathBuf, file_filter: F) -> Result<Self, Box<dyn Error>>
+    /// Create a new iterator with `path` as root, every folder sorted by `order`.
+    pub fn new<F>(path: PathBuf, file_filter: F, order: TreeOrder) -> Result<Self, WalkError>
     where
         F: FileFilter + 'static,
     {
-        let rc_filter = Rc::new(file_filter);
+        let settings = WalkSettings {
+            submodules: submodule_paths(&path),
+            canonical_root: path.canonicalize().unwrap_or_else(|_| path.clone()),
+            root: path,
+            file_filter: Arc::new(file_filter),
+            order,
+            limits: TreeLimits::default(),
+            symlinks: SymlinkPolicy::default(),
+        };
+
+        TreeIter {
+            dir_stack: vec![],
+            ancestors: vec![],
+            settings,
+        }
+        .restart()
+    }
+
+    /// Stop at a maximum depth and fold large folders, see [`TreeLimits`].
+    pub fn with_limits(mut self, limits: TreeLimits) -> Result<Self, WalkError> {
+        self.settings.limits = limits;
+        self.restart()
+    }
 
-        let p = path.clone();
+    /// Skip, show or follow symbolic links, see [`SymlinkPolicy`].
+    pub fn with_symlinks(mut self, symlinks: SymlinkPolicy) -> Result<Self, WalkError> {
+        self.settings.symlinks = symlinks;
+        self.restart()
+    }
 
-        fs::read_dir(p)
-            .map(|dir| {
-                let filtered = FilteredDir {
-                    file_filter: rc_filter.clone(),
-                    dir,
-                };
+    /// Read the root again with the current settings.
+    fn restart(mut self) -> Result<Self, WalkError> {
+        let ancestors = self.settings.ancestors_of(&[], &self.settings.root);
+        let root = FilteredDir::new(&self.settings.root, &self.settings, 1, &ancestors)?;
 
-                TreeIter {
-                    dir_stack: vec![filtered.peekable()],
-                    file_filter: rc_filter,
+        self.dir_stack = vec![root.peekable()];
+        self.ancestors = vec![ancestors];
+
+        Ok(self)
+    }
+
+    /// Walk the same tree asynchronously from where the iterator stands, the root read
+    /// by [`TreeIter::new`] included: folders are read in parallel ahead of the
+    /// consumer, events still come in the order of the iterator. Needs a tokio runtime.
+    pub fn into_stream(self) -> EventStream {
+        let open = self
+            .dir_stack
+            .into_iter()
+            .zip(self.ancestors)
+            .enumerate()
+            .map(|(index, (dir, ancestors))| (dir.collect::<Vec<_>>(), index + 1, ancestors))
+            .collect::<OpenDirs>();
+
+        let walker = Walker {
+            settings: Arc::new(self.settings),
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_LISTINGS)),
+        };
+
+        let state = StreamState {
+            open: Some((walker, open)),
+            dir_stack: vec![],
+        };
+
+        Box::pin(stream::unfold(state, |mut state| async move {
+            if let Some((walker, open)) = state.open.take() {
+                for (listed, depth, ancestors) in open {
+                    let nodes = walker.nodes(listed, depth, &ancestors);
+                    state.dir_stack.push(nodes.into_iter());
                 }
-            })
-            .map_err(From::from)
+            }
+
+            let dir = state.dir_stack.last_mut()?;
+
+            let event = match dir.next() {
+                Some(Ok(Node::File(entry))) => Ok(Event::File(entry)),
+                Some(Ok(Node::Folded(folded))) => Ok(Event::Folded(folded)),
+                Some(Ok(Node::Dir(entry, mut listing))) => match listing.join().await {
+                    Ok(dir) => {
+                        state.dir_stack.push(dir);
+                        Ok(Event::OpenDir(entry))
+                    }
+                    Err(err) => Err(err),
+                },
+                Some(Err(err)) => Err(err),
+                None => {
+                    state.dir_stack.pop();
+                    Ok(Event::CloseDir)
+                }
+            };
+
+            Some((event, state))
+        }))
     }
 }
 
@@ -139,60 +561,43 @@ fn has_next_sibling<T, E, I: Iterator<Item = Result<T, E>>>(dir: &mut Peekable<I
     }
 }
 
-fn next_entry(dir: &mut Peekable<FilteredDir>) -> Option<Result<Entry, Box<dyn Error>>> {
-    let entry = match dir.next() {
-        Some(Ok(entry)) => entry,
-        Some(Err(err)) => return Some(Err(From::from(err))),
-        None => return None,
-    };
-
-    let has_next_sibling = has_next_sibling(dir);
-    let metadata = match entry.metadata() {
-        Ok(metadata) => metadata,
-        Err(err) => return Some(Err(From::from(err))),
-    };
-    let path = entry.path();
-
-    Some(Ok(Entry {
-        path,
-        metadata,
-        has_next_sibling,
-    }))
-}
-
 impl Iterator for TreeIter {
-    type Item = Result<Event, Box<dyn Error>>;
+    type Item = Result<Event, WalkError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let entry;
-
-        loop {
-            match self.dir_stack.as_mut_slice().last_mut() {
-                Some(dir) => match next_entry(dir) {
-                    Some(Ok(the_entry)) => {
-                        entry = the_entry;
-                        break;
-                    }
-                    Some(Err(err)) => return Some(Err(err)),
-                    None => {}
-                },
-                None => return None,
-            };
+        let dir = self.dir_stack.last_mut()?;
 
-            self.dir_stack.pop();
-            return Some(Ok(Event::CloseDir));
-        }
+        let mut entry = match dir.next() {
+            Some(Ok(Listed::Entry(entry))) => entry,
+            Some(Ok(Listed::Folded(folded))) => return Some(Ok(Event::Folded(folded))),
+            Some(Err(err)) => return Some(Err(err)),
+            None => {
+                self.dir_stack.pop();
+                self.ancestors.pop();
+                return Some(Ok(Event::CloseDir));
+            }
+        };
 
-        if entry.metadata.is_dir() {
-            match FilteredDir::new(&entry.path, self.file_filter.clone()) {
-                Ok(dir) => self.dir_stack.push(dir.peekable()),
-                Err(err) => return Some(Err(From::from(err))),
-            };
+        entry.has_next_sibling = has_next_sibling(dir);
 
-            Some(Ok(Event::OpenDir(entry)))
-        } else {
-            Some(Ok(Event::File(entry)))
+        if entry.kind != EntryKind::Dir {
+            return Some(Ok(Event::File(entry)));
         }
+
+        let depth = self.dir_stack.len() + 1;
+        let ancestors = self
+            .settings
+            .ancestors_of(self.ancestors.last().unwrap(), &entry.path);
+
+        match FilteredDir::new(&entry.path, &self.settings, depth, &ancestors) {
+            Ok(dir) => {
+                self.dir_stack.push(dir.peekable());
+                self.ancestors.push(ancestors);
+            }
+            Err(err) => return Some(Err(err)),
+        };
+
+        Some(Ok(Event::OpenDir(entry)))
     }
 }
 
@@ -201,8 +606,15 @@ pub trait TreeProcessor {
 
     fn construct_dir(&mut self, entry: &Entry) -> String;
     fn construct_file(&mut self, entry: &Entry) -> String;
+    fn construct_folded(&mut self, folded: &Folded) -> String;
+
+    /// Called once the whole tree was walked, formats that can't be streamed
+    /// (JSON, YAML) render everything here.
+    fn finish(&mut self) -> String {
+        String::new()
+    }
 
-    fn construct(&mut self, tree: &mut TreeIter) -> Result<String, Box<dyn Error>> {
+    fn construct(&mut self, tree: &mut TreeIter) -> Result<String, WalkError> {
         let mut result = String::new();
         for event in tree {
             match event {
@@ -210,6 +622,9 @@ pub trait TreeProcessor {
                     match event {
                         Event::OpenDir(ref entry) => result.push_str(&self.construct_dir(entry)),
                         Event::File(ref entry) => result.push_str(&self.construct_file(entry)),
+                        Event::Folded(ref folded) => {
+                            result.push_str(&self.construct_folded(folded))
+                        }
                         Event::CloseDir => self.close_dir(),
                     };
                 }
@@ -217,6 +632,8 @@ pub trait TreeProcessor {
             };
         }
 
+        result.push_str(&self.finish());
+
         Ok(result)
     }
 }
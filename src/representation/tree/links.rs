@@ -0,0 +1,59 @@
+use std::collections::HashSet;
+use std::fs::FileType;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+use git2::Repository;
+use serde_derive::{Deserialize, Serialize};
+
+/// What the walk does with symbolic links.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    /// Leave links out of the tree.
+    Skip,
+    /// List links as leaves, with the path they point to.
+    #[default]
+    Show,
+    /// Walk links like the files and folders they point to, a link back to one of
+    /// its parents or out of the project is listed as a leaf instead.
+    Follow,
+}
+
+/// Sockets, FIFOs and devices are never part of the tree.
+#[cfg(unix)]
+pub fn is_special(file_type: &FileType) -> bool {
+    file_type.is_socket()
+        || file_type.is_fifo()
+        || file_type.is_block_device()
+        || file_type.is_char_device()
+}
+
+/// Other platforms only have files, folders and links in a walk.
+#[cfg(not(unix))]
+pub fn is_special(_file_type: &FileType) -> bool {
+    false
+}
+
+/// Canonical paths of the submodules of the repository `root` belongs to, empty
+/// outside of a repository.
+pub fn submodule_paths(root: &Path) -> HashSet<PathBuf> {
+    let Ok(repository) = Repository::discover(root) else {
+        return HashSet::new();
+    };
+
+    let Some(workdir) = repository.workdir() else {
+        return HashSet::new();
+    };
+
+    repository
+        .submodules()
+        .map(|submodules| {
+            submodules
+                .iter()
+                .filter_map(|submodule| workdir.join(submodule.path()).canonicalize().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
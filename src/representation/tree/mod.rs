@@ -1,4 +1,8 @@
+pub mod annotations;
 pub mod filters;
+pub mod folding;
+pub mod formats;
 pub mod iterator;
-pub mod reader;
+pub mod links;
+pub mod order;
 pub mod representation;
@@ -0,0 +1,164 @@
+use std::cmp::Ordering;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::iterator::Entry;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    /// Byte order of the names, `B.txt` before `a.txt` and `file10` before `file2`.
+    Name,
+    /// Case insensitive, with numbers compared by value: `file2` before `file10`.
+    #[default]
+    Natural,
+}
+
+/// Order of the entries of every folder, so the same project always gives the same tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeOrder {
+    pub sort: SortOrder,
+    pub dirs_first: bool,
+}
+
+impl Default for TreeOrder {
+    fn default() -> Self {
+        TreeOrder {
+            sort: SortOrder::Natural,
+            dirs_first: true,
+        }
+    }
+}
+
+impl TreeOrder {
+    /// Submodules and followed links to folders sort with the folders, links that
+    /// aren't followed with the files.
+    pub fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+        let by_kind = match self.dirs_first {
+            true => b.is_dir().cmp(&a.is_dir()),
+            false => Ordering::Equal,
+        };
+
+        by_kind.then_with(|| {
+            let (a, b) = (a.path().file_name(), b.path().file_name());
+            let (a, b) = (
+                a.unwrap_or_default().to_string_lossy(),
+                b.unwrap_or_default().to_string_lossy(),
+            );
+
+            match self.sort {
+                SortOrder::Name => a.cmp(&b),
+                SortOrder::Natural => natural_cmp(&a, &b),
+            }
+        })
+    }
+}
+
+/// Compare runs of digits by value and everything else case insensitively,
+/// falling back to byte order so different names never compare equal.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut left, mut right) = (a.chars().peekable(), b.chars().peekable());
+
+    loop {
+        match (left.peek().copied(), right.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(l), Some(r)) if l.is_ascii_digit() && r.is_ascii_digit() => {
+                let l = take_number(&mut left);
+                let r = take_number(&mut right);
+
+                let (l_digits, r_digits) = (l.trim_start_matches('0'), r.trim_start_matches('0'));
+
+                let ordering = l_digits
+                    .len()
+                    .cmp(&r_digits.len())
+                    .then_with(|| l_digits.cmp(r_digits));
+
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(l), Some(r)) => {
+                let ordering = l.to_lowercase().cmp(r.to_lowercase());
+
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+
+                left.next();
+                right.next();
+            }
+        }
+    }
+}
+
+fn take_number<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) -> String {
+    let mut number = String::new();
+
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        number.push(c);
+    }
+
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::natural_cmp;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names = names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn numbers_are_compared_by_value() {
+        assert_eq!(
+            sorted(&["file10.txt", "file2.txt", "file1.txt", "file1b.txt"]),
+            ["file1.txt", "file1b.txt", "file2.txt", "file10.txt"]
+        );
+        assert_eq!(
+            sorted(&["v1.10", "v1.9", "v1.2"]),
+            ["v1.2", "v1.9", "v1.10"]
+        );
+    }
+
+    #[test]
+    fn case_is_ignored_until_the_names_only_differ_by_it() {
+        assert_eq!(
+            sorted(&["b.txt", "B.txt", "a.txt", "Cargo.toml"]),
+            ["a.txt", "B.txt", "b.txt", "Cargo.toml"]
+        );
+    }
+
+    #[test]
+    fn leading_zeros_never_make_names_equal() {
+        assert_eq!(
+            sorted(&["file002", "file2", "file3"]),
+            ["file002", "file2", "file3"]
+        );
+        assert_ne!(natural_cmp("file02", "file2"), Ordering::Equal);
+        assert_eq!(natural_cmp("file", "file"), Ordering::Equal);
+    }
+
+    #[test]
+    fn prefixes_sort_first() {
+        assert_eq!(natural_cmp("lib", "lib.rs"), Ordering::Less);
+        assert_eq!(natural_cmp("file1", "file"), Ordering::Greater);
+    }
+
+    #[test]
+    fn long_numbers_do_not_overflow() {
+        assert_eq!(
+            natural_cmp("run99999999999999999999999", "run100000000000000000000000"),
+            Ordering::Less
+        );
+    }
+}
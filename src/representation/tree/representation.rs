@@ -1,30 +1,79 @@
 use std::borrow::Cow;
 use std::fmt::Display;
 use std::path::Path;
+use std::sync::Arc;
 
+use super::annotations::{leaf_name, suffix, Annotations};
+use super::folding::Folded;
 use super::iterator::{Entry, TreeProcessor};
 
+/// Line prefixes drawing the branches of the tree.
+pub struct TreeCharset {
+    pub vertical: &'static str,
+    pub branched: &'static str,
+    pub terminal: &'static str,
+    pub empty: &'static str,
+}
+
+pub const BOX_CHARSET: TreeCharset = TreeCharset {
+    vertical: "│   ",
+    branched: "├── ",
+    terminal: "└── ",
+    empty: "    ",
+};
+
+/// Same layout as `tree --charset=ascii`, for models that mangle box-drawing characters.
+pub const ASCII_CHARSET: TreeCharset = TreeCharset {
+    vertical: "|   ",
+    branched: "|-- ",
+    terminal: "`-- ",
+    empty: "    ",
+};
+
 pub struct TreeRepresentation {
+    charset: &'static TreeCharset,
     dir_has_next: Vec<bool>,
     num_dirs: usize,
     num_files: usize,
-    // summary_format: SummaryFormat,
+    num_folded: usize,
+    annotations: Option<Arc<Annotations>>,
 }
 
 impl TreeRepresentation {
     pub fn new() -> Self {
+        TreeRepresentation::with_charset(&BOX_CHARSET)
+    }
+
+    pub fn ascii() -> Self {
+        TreeRepresentation::with_charset(&ASCII_CHARSET)
+    }
+
+    fn with_charset(charset: &'static TreeCharset) -> Self {
         TreeRepresentation {
+            charset,
             dir_has_next: vec![true],
             num_dirs: 0,
             num_files: 0,
+            num_folded: 0,
+            annotations: None,
         }
     }
 
+    /// Follow every file name with its size, lines, language and summary.
+    pub fn annotated(mut self, annotations: Option<Arc<Annotations>>) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    fn label(&self, entry: &Entry) -> String {
+        leaf_name(entry) + &suffix(self.annotations.as_deref(), entry)
+    }
+
     fn construct_entry<D: Display>(&mut self, name: &D) -> String {
-        let vertical_line = "│   ";
-        let branched_line = "├── ";
-        let terminal_line = "└── ";
-        let empty_line = "    ";
+        let vertical_line = self.charset.vertical;
+        let branched_line = self.charset.branched;
+        let terminal_line = self.charset.terminal;
+        let empty_line = self.charset.empty;
 
         let mut entry = String::new();
 
@@ -44,13 +93,13 @@ impl TreeRepresentation {
             }
         }
 
-        entry.push_str(&format!("{}\n", name.to_string()));
+        entry.push_str(&format!("{}\n", name));
 
         entry
     }
 }
 
-fn file_name_from_path(path: &Path) -> Cow<str> {
+pub(crate) fn file_name_from_path(path: &Path) -> Cow<'_, str> {
     path.file_name().unwrap().to_string_lossy()
 }
 
@@ -65,7 +114,7 @@ impl TreeProcessor for TreeRepresentation {
         if self.dir_has_next.is_empty() {
             dir.push_str(&self.construct_entry(&entry.path().display()));
         } else {
-            dir.push_str(&self.construct_entry(&file_name_from_path(entry.path()).to_string()));
+            dir.push_str(&self.construct_entry(&self.label(entry)));
         };
 
         self.dir_has_next.push(true);
@@ -84,9 +133,41 @@ impl TreeProcessor for TreeRepresentation {
         self.dir_has_next.pop();
         self.dir_has_next.push(entry.has_next_sibling());
 
-        let file = self.construct_entry(&file_name_from_path(entry.path()).to_string());
-        self.num_files += 1;
+        let file = self.construct_entry(&self.label(entry));
+
+        // Submodules are folders, listed without their content.
+        match entry.is_dir() {
+            true => self.num_dirs += 1,
+            false => self.num_files += 1,
+        }
 
         file
     }
+
+    fn construct_folded(&mut self, folded: &Folded) -> String {
+        self.dir_has_next.pop();
+        self.dir_has_next.push(false);
+
+        self.num_folded += folded.files + folded.dirs;
+
+        self.construct_entry(folded)
+    }
+
+    /// Footer in the style of `tree`, e.g. `3 directories, 12 files`.
+    fn finish(&mut self) -> String {
+        let directories = match self.num_dirs {
+            1 => "1 directory".to_string(),
+            count => format!("{count} directories"),
+        };
+
+        let files = match self.num_files {
+            1 => "1 file".to_string(),
+            count => format!("{count} files"),
+        };
+
+        match self.num_folded {
+            0 => format!("\n{directories}, {files}\n"),
+            folded => format!("\n{directories}, {files}, {folded} folded\n"),
+        }
+    }
 }
@@ -0,0 +1,62 @@
+use std::fs::{create_dir_all, read_to_string, remove_file, set_permissions, write, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+use crate::structure::software::Project;
+
+const HOOK_MARKER: &str = "# Installed by `blob hooks install`";
+
+fn prepare_commit_msg_hook() -> String {
+    format!(
+        "#!/bin/sh
+{HOOK_MARKER}, remove it with `blob hooks uninstall`.
+# Proposes a message for plain `git commit` runs, messages given with -m, merges,
+# squashes and amends are left alone.
+[ -n \"$2\" ] && exit 0
+command -v blob >/dev/null 2>&1 || exit 0
+blob commit-msg --with-definitions --output \"$1\" || true
+"
+    )
+}
+
+fn prepare_commit_msg_path(project: &Project) -> PathBuf {
+    project.hooks_dir().join("prepare-commit-msg")
+}
+
+/// Install the `prepare-commit-msg` hook, an existing hook is only replaced with `force`.
+pub fn install_prepare_commit_msg(project: &Project, force: bool) -> Result<PathBuf> {
+    let hook_path = prepare_commit_msg_path(project);
+
+    if let Ok(existing) = read_to_string(&hook_path) {
+        if !existing.contains(HOOK_MARKER) && !force {
+            return Err(anyhow!(
+                "{} already exists, use --force to replace it",
+                hook_path.display()
+            ));
+        }
+    }
+
+    if let Some(hooks_dir) = hook_path.parent() {
+        create_dir_all(hooks_dir)?;
+    }
+
+    write(&hook_path, prepare_commit_msg_hook())?;
+    set_permissions(&hook_path, Permissions::from_mode(0o755))?;
+
+    Ok(hook_path)
+}
+
+/// Remove the hook if it was installed by blob, returns the removed path.
+pub fn uninstall_prepare_commit_msg(project: &Project) -> Result<Option<PathBuf>> {
+    let hook_path = prepare_commit_msg_path(project);
+
+    match read_to_string(&hook_path) {
+        Ok(existing) if existing.contains(HOOK_MARKER) => {
+            remove_file(&hook_path)?;
+            Ok(Some(hook_path))
+        }
+        _ => Ok(None),
+    }
+}
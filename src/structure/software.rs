@@ -1,6 +1,13 @@
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 
-use git2::Repository;
+use anyhow::{anyhow, Result};
+use git2::build::CheckoutBuilder;
+use git2::{
+    BranchType, Delta, Diff, DiffFindOptions, DiffOptions, Index, IndexAddOption, ObjectType, Oid,
+    Patch, Repository, Signature, Status, StatusOptions, Tree,
+};
+use serde_derive::{Deserialize, Serialize};
 use tokio::fs;
 
 #[derive(Clone, Debug)]
@@ -18,6 +25,34 @@ where
     Dir(PathBuf, Vec<SourceAtomTyped<T>>, T),
 }
 
+/// Which changes to look at, mirrors `git diff`, `git diff --staged` and `git diff <range>`.
+#[derive(Clone, Debug)]
+pub enum DiffTarget {
+    WorkingTree,
+    Staged,
+    Range(String),
+}
+
+/// The changes of a single file, the patch lines are prefixed with their line
+/// number in the new version of the file so comments can be anchored to them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileDiff {
+    pub path: String,
+    pub status: String,
+    pub patch: String,
+}
+
+impl FileDiff {
+    /// The patch cut at `max_chars`, a generated or vendored file can be far bigger
+    /// than what fits in a prompt.
+    pub fn truncated_patch(&self, max_chars: usize) -> String {
+        match self.patch.char_indices().nth(max_chars) {
+            Some((end, _)) => format!("{}\n[... diff truncated]", &self.patch[..end]),
+            None => self.patch.clone(),
+        }
+    }
+}
+
 pub struct Project {
     pub root_path: PathBuf,
     repository: Repository,
@@ -25,14 +60,361 @@ pub struct Project {
 }
 
 impl Project {
-    pub fn new(root_path: PathBuf) -> Self {
-        let repository = Repository::discover(root_path.clone()).unwrap();
+    pub fn new(root_path: PathBuf) -> Result<Self> {
+        let repository = Repository::discover(root_path.clone()).map_err(|err| {
+            anyhow!(
+                "'{}' is not inside a git repository: {}",
+                root_path.display(),
+                err.message()
+            )
+        })?;
 
-        Project {
+        Ok(Project {
             root_path,
-            repository: repository,
+            repository,
             // source: vec![],
+        })
+    }
+
+    fn head_tree(&self) -> Result<Option<Tree<'_>>> {
+        match self.repository.head() {
+            Ok(head) => Ok(Some(head.peel_to_tree()?)),
+            // A repository without commits yet, everything is new.
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn diff(&self, target: &DiffTarget, context_lines: u32) -> Result<Vec<FileDiff>> {
+        let mut options = DiffOptions::new();
+        options
+            .context_lines(context_lines)
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+
+        let repo = &self.repository;
+
+        let mut diff = match target {
+            DiffTarget::WorkingTree => {
+                let head = self.head_tree()?;
+                repo.diff_tree_to_workdir_with_index(head.as_ref(), Some(&mut options))?
+            }
+            DiffTarget::Staged => {
+                let head = self.head_tree()?;
+                repo.diff_tree_to_index(head.as_ref(), None, Some(&mut options))?
+            }
+            DiffTarget::Range(range) if range.contains("..") => {
+                let spec = repo.revparse(range)?;
+
+                let from = match spec.from() {
+                    Some(from) => Some(from.peel_to_tree()?),
+                    None => None,
+                };
+                let to = match spec.to() {
+                    Some(to) => Some(to.peel_to_tree()?),
+                    None => None,
+                };
+
+                repo.diff_tree_to_tree(from.as_ref(), to.as_ref(), Some(&mut options))?
+            }
+            DiffTarget::Range(rev) => {
+                let tree = repo.revparse_single(rev)?.peel_to_tree()?;
+                repo.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut options))?
+            }
+        };
+
+        // Pair deleted and added files into renames, untracked files included since
+        // a file moved in the working tree is untracked until it is staged.
+        diff.find_similar(Some(
+            DiffFindOptions::new().renames(true).for_untracked(true),
+        ))?;
+
+        file_diffs(&diff)
+    }
+
+    /// The `.git` directory (or the worktree's git directory).
+    pub fn git_dir(&self) -> PathBuf {
+        self.repository.path().to_path_buf()
+    }
+
+    /// Where git looks for hooks, honoring `core.hooksPath`.
+    pub fn hooks_dir(&self) -> PathBuf {
+        let configured = self
+            .repository
+            .config()
+            .and_then(|config| config.get_path("core.hooksPath"));
+
+        match configured {
+            Ok(path) if path.is_absolute() => path,
+            Ok(path) => self
+                .repository
+                .workdir()
+                .map(|workdir| workdir.join(&path))
+                .unwrap_or(path),
+            Err(_) => self.git_dir().join("hooks"),
+        }
+    }
+
+    /// Path of `path` relative to the repository work directory.
+    pub fn relative_path(&self, path: &str) -> Option<String> {
+        let workdir = std::path::absolute(self.repository.workdir()?).ok()?;
+        let path = std::path::absolute(path).ok()?;
+
+        path.strip_prefix(workdir)
+            .ok()
+            .map(|relative| relative.to_string_lossy().to_string())
+    }
+
+    /// Every path that differs from `HEAD`, with its status.
+    pub fn dirty_paths(&self) -> Result<BTreeMap<String, Status>> {
+        let mut options = StatusOptions::new();
+        options.include_untracked(true).recurse_untracked_dirs(true);
+
+        let statuses = self.repository.statuses(Some(&mut options))?;
+
+        Ok(statuses
+            .iter()
+            .filter_map(|entry| Some((entry.path()?.to_string(), entry.status())))
+            .collect())
+    }
+
+    /// Id of the blob the working tree file at `path` would be stored as.
+    pub fn working_file_id(&self, path: &str) -> Option<Oid> {
+        let workdir = self.repository.workdir()?;
+
+        Oid::hash_file(ObjectType::Blob, workdir.join(path)).ok()
+    }
+
+    pub fn stage_paths(&self, paths: &[String]) -> Result<()> {
+        let mut index = self.repository.index()?;
+        let workdir = self
+            .repository
+            .workdir()
+            .ok_or_else(|| anyhow!("The repository has no work directory"))?;
+
+        for path in paths {
+            if workdir.join(path).exists() {
+                index.add_all([path.as_str()], IndexAddOption::DEFAULT, None)?;
+            } else {
+                index.remove_all([path.as_str()], None)?;
+            }
+        }
+
+        index.write()?;
+
+        Ok(())
+    }
+
+    /// Patch text of the staged changes, capped to `max_chars`.
+    pub fn staged_patch(&self, max_chars: usize) -> Result<String> {
+        Ok(truncated_patch(
+            &self.diff(&DiffTarget::Staged, 3)?,
+            max_chars,
+        ))
+    }
+
+    /// Create `name` at the current `HEAD` and switch to it, keeping the working tree as is.
+    pub fn create_and_switch_branch(&self, name: &str) -> Result<()> {
+        let head = self.repository.head()?.peel_to_commit()?;
+
+        let branch = self.repository.branch(name, &head, false)?;
+        let reference = branch
+            .get()
+            .name()
+            .ok_or_else(|| anyhow!("Invalid branch name '{name}'"))?;
+
+        self.repository.set_head(reference)?;
+
+        Ok(())
+    }
+
+    fn signature(&self) -> Result<Signature<'static>> {
+        self.repository
+            .signature()
+            .or_else(|_| Signature::now("blob", "blob@localhost"))
+            .map_err(From::from)
+    }
+
+    /// Tree of `HEAD` with only `paths` taken from the working tree, built in a
+    /// temporary index so whatever the user staged stays out of it.
+    pub fn tree_with_paths(&self, paths: &[String]) -> Result<Oid> {
+        let repository = Repository::open(self.repository.path())?;
+        let workdir = repository
+            .workdir()
+            .ok_or_else(|| anyhow!("The repository has no work directory"))?
+            .to_path_buf();
+
+        let mut index = Index::new()?;
+        repository.set_index(&mut index)?;
+
+        if let Some(head) = self.head_tree()? {
+            index.read_tree(&head)?;
+        }
+
+        for path in paths {
+            match workdir.join(path).symlink_metadata() {
+                Ok(_) => index.add_path(Path::new(path))?,
+                Err(_) => index.remove_path(Path::new(path))?,
+            }
         }
+
+        Ok(index.write_tree()?)
+    }
+
+    /// Patch text between `HEAD` and `tree`, capped to `max_chars`.
+    pub fn tree_patch(&self, tree: Oid, max_chars: usize) -> Result<String> {
+        let tree = self.repository.find_tree(tree)?;
+        let head = self.head_tree()?;
+
+        let mut options = DiffOptions::new();
+        options.context_lines(3);
+
+        let diff =
+            self.repository
+                .diff_tree_to_tree(head.as_ref(), Some(&tree), Some(&mut options))?;
+
+        Ok(truncated_patch(&file_diffs(&diff)?, max_chars))
+    }
+
+    /// Commit `tree` on top of `HEAD` and return the new commit id.
+    pub fn commit_tree(&self, tree: Oid, message: &str) -> Result<String> {
+        let tree = self.repository.find_tree(tree)?;
+
+        let signature = self.signature()?;
+
+        let parent = match self.repository.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+
+        let oid = self.repository.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )?;
+
+        Ok(oid.to_string())
+    }
+
+    /// Paths changed by `commit` compared to its first parent.
+    fn commit_paths(&self, commit: &git2::Commit) -> Result<Vec<String>> {
+        let parent = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+
+        let diff =
+            self.repository
+                .diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+
+        Ok(diff
+            .deltas()
+            .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+            .flatten()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect())
+    }
+
+    /// Revert `commit_id` with a new commit on `branch`, or on `HEAD` when no branch
+    /// is given, and return its id. The revert is computed in memory, the working
+    /// tree is only updated when the reverted branch is the one checked out.
+    pub fn revert_commit(
+        &self,
+        commit_id: &str,
+        branch: Option<&str>,
+        message: &str,
+    ) -> Result<String> {
+        let repository = &self.repository;
+
+        let commit = repository.find_commit(Oid::from_str(commit_id)?)?;
+
+        let reference = match branch {
+            Some(branch) => repository
+                .find_branch(branch, BranchType::Local)
+                .map_err(|_| anyhow!("The branch '{branch}' doesn't exist anymore"))?
+                .into_reference(),
+            None => repository.head()?,
+        };
+
+        let reference_name = reference
+            .name()
+            .ok_or_else(|| anyhow!("The branch has an invalid name"))?
+            .to_string();
+
+        let target = reference.peel_to_commit()?;
+
+        if target.id() != commit.id()
+            && !repository.graph_descendant_of(target.id(), commit.id())?
+        {
+            return Err(anyhow!(
+                "{} doesn't contain {commit_id}, check out the branch it was committed on",
+                reference.shorthand().unwrap_or(&reference_name)
+            ));
+        }
+
+        let mut index = repository.revert_commit(&commit, &target, 0, None)?;
+
+        if index.has_conflicts() {
+            return Err(anyhow!(
+                "reverting {commit_id} conflicts with the changes made since"
+            ));
+        }
+
+        let tree = repository.find_tree(index.write_tree_to(repository)?)?;
+
+        let checked_out = repository
+            .head()
+            .ok()
+            .and_then(|head| head.name().map(str::to_string))
+            == Some(reference_name.clone());
+
+        if checked_out {
+            let paths = self.commit_paths(&commit)?;
+            let dirty = self.dirty_paths()?;
+
+            if dirty.values().any(|status| is_staged(*status)) {
+                return Err(anyhow!(
+                    "the index has staged changes, commit or unstage them first"
+                ));
+            }
+
+            if let Some(path) = paths.iter().find(|path| dirty.contains_key(*path)) {
+                return Err(anyhow!(
+                    "{path} has uncommitted changes, commit or stash them first"
+                ));
+            }
+
+            let mut checkout = CheckoutBuilder::new();
+            checkout.safe();
+
+            for path in &paths {
+                checkout.path(path);
+            }
+
+            repository.checkout_tree(tree.as_object(), Some(&mut checkout))?;
+        }
+
+        let signature = self.signature()?;
+
+        let oid = repository.commit(
+            Some(&reference_name),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &[&target],
+        )?;
+
+        Ok(oid.to_string())
     }
 
     pub async fn calculate_source<T>(
@@ -116,3 +498,105 @@ impl Project {
         source
     }
 }
+
+/// Whether `status` has a change staged in the index.
+pub fn is_staged(status: Status) -> bool {
+    status.intersects(
+        Status::INDEX_NEW
+            | Status::INDEX_MODIFIED
+            | Status::INDEX_DELETED
+            | Status::INDEX_RENAMED
+            | Status::INDEX_TYPECHANGE,
+    )
+}
+
+fn truncated_patch(file_diffs: &[FileDiff], max_chars: usize) -> String {
+    let patch = file_diffs
+        .iter()
+        .map(|file_diff| {
+            format!(
+                "--- {} ({})\n{}",
+                file_diff.path, file_diff.status, file_diff.patch
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n\n");
+
+    match patch.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}\n[... diff truncated]", &patch[..end]),
+        None => patch,
+    }
+}
+
+fn file_diffs(diff: &Diff) -> Result<Vec<FileDiff>> {
+    let mut file_diffs = Vec::new();
+
+    for (idx, delta) in diff.deltas().enumerate() {
+        let path = delta
+            .new_file()
+            .path()
+            .or_else(|| delta.old_file().path())
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let status = match delta.status() {
+            Delta::Added | Delta::Untracked => "added".to_string(),
+            Delta::Deleted => "deleted".to_string(),
+            Delta::Renamed => format!(
+                "renamed from {}",
+                delta
+                    .old_file()
+                    .path()
+                    .map(|path| path.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            ),
+            _ => "modified".to_string(),
+        };
+
+        let patch = match Patch::from_diff(diff, idx)? {
+            Some(patch) => patch,
+            // Binary files have no textual patch.
+            None => continue,
+        };
+
+        let mut lines = Vec::new();
+
+        for hunk_idx in 0..patch.num_hunks() {
+            let (hunk, lines_in_hunk) = patch.hunk(hunk_idx)?;
+
+            lines.push(
+                String::from_utf8_lossy(hunk.header())
+                    .trim_end()
+                    .to_string(),
+            );
+
+            for line_idx in 0..lines_in_hunk {
+                let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+
+                let line_number = line
+                    .new_lineno()
+                    .map(|number| number.to_string())
+                    .unwrap_or_default();
+
+                lines.push(format!(
+                    "{:>5} {}{}",
+                    line_number,
+                    line.origin(),
+                    String::from_utf8_lossy(line.content()).trim_end()
+                ));
+            }
+        }
+
+        if lines.is_empty() {
+            continue;
+        }
+
+        file_diffs.push(FileDiff {
+            path,
+            status,
+            patch: lines.join("\n"),
+        });
+    }
+
+    Ok(file_diffs)
+}